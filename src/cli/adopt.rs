@@ -0,0 +1,369 @@
+use aws_config::{self, BehaviorVersion};
+use aws_sdk_cloudformation::types::ResourceToImport;
+use aws_sdk_cloudformation::Client;
+use aws_types::region::Region;
+use clap::ArgMatches;
+use clap::{arg, Command};
+use colored::Colorize;
+use log;
+use std::collections::HashMap;
+use std::env;
+use std::time::Instant;
+
+use crate::apply::{self, StackInput};
+use crate::config;
+use crate::output;
+use crate::parameters;
+use crate::plan;
+use crate::stacks;
+use crate::template;
+use crate::utils;
+use utils::stack_request_result_handle;
+
+const ABOUT: &str = r#"adopt existing resources into a stack,
+using a cloudformation import change set
+"#;
+
+// ResourceSpec is a resource to import, given on the command line as
+// LogicalId=Type:identifier, for eg: Bucket=AWS::S3::Bucket:my-bucket
+#[derive(Debug, PartialEq)]
+pub struct ResourceSpec {
+    pub logical_id: String,
+    pub resource_type: String,
+    // identifier properties of the resource. A bare value is recorded
+    // without a key and matched to the identifier property of the type
+    pub identifier: Vec<(Option<String>, String)>,
+}
+
+pub fn command() -> Command {
+    Command::new("adopt")
+        .about(ABOUT.truecolor(125, 174, 189).to_string())
+        .arg(arg!(<stack> "name of the stack to import the resources into"))
+        .arg(
+            arg!(-r --resource <RESOURCE> "resource to import, as LogicalId=Type:identifier")
+                .required(true)
+                .action(clap::ArgAction::Append),
+        )
+        .arg(arg!(-y --yes "import without asking for confirmation"))
+        .arg(arg!(-c --config <FILE> "path to config file"))
+}
+
+pub async fn handle(matches: &ArgMatches) -> Result<(), String> {
+    let mut config_path = env::var("KLOI_CONFIG").ok();
+
+    // if config is not set by env, check if it is set by cli
+    if config_path.is_none() {
+        log::debug!("config path is not set by env, KLOI_CONFIG, check CLI -c/--config");
+        config_path = Some(matches
+            .get_one::<String>("config")
+            .ok_or_else(|| "config file required, please supply using -c/--config or set the KLOI_CONFIG env var".to_string())?.to_string());
+    };
+
+    // load config and create client
+    // note: unwrap is fine here, since we've already checked if config is set above
+    let conf = config::load_config_from_file(config_path.unwrap())?;
+
+    // note: unwraps are fine here, the arguments are required
+    let name = matches.get_one::<String>("stack").unwrap();
+    let mut stack = conf
+        .stacks
+        .iter()
+        .find(|s| &s.name == name)
+        .ok_or_else(|| format!("stack [{}] not found", name))?
+        .clone();
+
+    let specs = matches
+        .get_many::<String>("resource")
+        .unwrap()
+        .map(|r| ResourceSpec::parse(r))
+        .collect::<Result<Vec<ResourceSpec>, String>>()?;
+
+    apply::prompt_missing_parameters(&mut stack)?;
+    parameters::validate_stack(&stack)?;
+
+    let started = Instant::now();
+    let adopted = adopt_stack(&stack, specs, matches.get_flag("yes")).await;
+
+    let mut report = output::Report::new("adopt");
+    report
+        .stacks
+        .push(output::StackResult::from_outcome(&stack, &adopted, started));
+
+    let result = adopted.map(|_| ());
+    report.emit(&result)?;
+    result
+}
+
+// adopt_stack imports the given resources into a stack
+async fn adopt_stack(
+    stack: &stacks::Stack,
+    specs: Vec<ResourceSpec>,
+    yes: bool,
+) -> Result<output::Outcome, String> {
+    // imported resources must be declared in the template
+    // before the change set is requested
+    let rendered = stack.generate_template()?;
+    let parsed = template::parse(&rendered).map_err(|e| format!("[{}] {}", stack.name, e))?;
+    let issues: Vec<String> = specs
+        .iter()
+        .filter_map(|r| template::check_import(&parsed, &r.logical_id, &r.resource_type).err())
+        .map(|e| format!("[{}] {}", stack.name, e))
+        .collect();
+    if !issues.is_empty() {
+        return Err(issues.join("\n"));
+    }
+
+    let region = stack.region.clone().unwrap_or("eu-west-1".to_string());
+    let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+        .region(Region::new(region.clone()))
+        .load()
+        .await;
+    let client = aws_sdk_cloudformation::Client::new(&sdk_config);
+
+    let deployed = utils::describe_stack(&client, &stack.name).await?;
+    let exists = deployed.is_some();
+    let input = StackInput::new(stack, &sdk_config, deployed.as_ref()).await?;
+
+    let keys = identifier_keys(&client, stack, &input).await?;
+    let mut resources = Vec::new();
+    for spec in specs.iter() {
+        let identifier = spec
+            .resource_identifier(keys.get(&spec.resource_type).unwrap_or(&Vec::new()))
+            .map_err(|e| format!("[{}] {}", stack.name, e))?;
+
+        resources.push(
+            ResourceToImport::builder()
+                .resource_type(&spec.resource_type)
+                .logical_resource_id(&spec.logical_id)
+                .set_resource_identifier(Some(identifier))
+                .build(),
+        );
+    }
+
+    let Some(cs) =
+        plan::create_import_change_set(&client, stack, &input, exists, resources).await?
+    else {
+        return Ok(output::Outcome::Unchanged);
+    };
+
+    plan::print_changes(&cs);
+    let approved = utils::approve(&format!("[{}] import resources?", stack.name), yes);
+    if !matches!(approved, Ok(true)) {
+        // the change set is removed whether the import was declined or
+        // couldn't be confirmed
+        plan::delete_change_set(&client, &cs).await?;
+        approved?;
+        log::info!("[{}] {}", stack.name.cyan(), "import declined".yellow());
+        return Ok(output::Outcome::Declined);
+    }
+
+    let token = utils::request_token();
+    let res = client
+        .execute_change_set()
+        .change_set_name(&cs.id)
+        .client_request_token(&token)
+        .send()
+        .await;
+
+    stack_request_result_handle!(res, stack.name, "execute import change set");
+
+    utils::stackprogress(
+        &client,
+        &stack.name,
+        stack.custom_resources.clone(),
+        region,
+        utils::WaitEvent::Import,
+        Some(&token),
+    )
+    .await?;
+
+    apply::update_termination_protection(&client, stack).await?;
+    Ok(output::Outcome::Imported)
+}
+
+// identifier_keys returns the identifier properties of every resource
+// type in the template, for eg: AWS::S3::Bucket => [BucketName]
+async fn identifier_keys(
+    client: &Client,
+    s: &stacks::Stack,
+    input: &StackInput,
+) -> Result<HashMap<String, Vec<String>>, String> {
+    let res = client
+        .get_template_summary()
+        .set_template_body(input.template_body())
+        .set_template_url(input.template_url.clone())
+        .send()
+        .await
+        .map_err(|e| {
+            format!(
+                "[{}] error getting template summary: {}",
+                s.name,
+                e.into_service_error()
+            )
+        })?;
+
+    Ok(res
+        .resource_identifier_summaries()
+        .iter()
+        .filter_map(|r| {
+            Some((
+                r.resource_type()?.to_string(),
+                r.resource_identifiers().to_vec(),
+            ))
+        })
+        .collect())
+}
+
+impl ResourceSpec {
+    // parse reads a resource spec such as Bucket=AWS::S3::Bucket:my-bucket.
+    // Resources identified by several properties are given as
+    // Key=Value pairs, for eg: Attachment=AWS::EC2::VPCGatewayAttachment:VpcId=vpc-1,InternetGatewayId=igw-1
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let err = || {
+            format!(
+                "invalid resource [{}], expected LogicalId=Type:identifier, for eg: Bucket=AWS::S3::Bucket:my-bucket",
+                spec
+            )
+        };
+
+        let (logical_id, rest) = spec.split_once('=').ok_or_else(err)?;
+
+        // types have the form Vendor::Service::Resource, the identifier
+        // follows the first single colon and may contain colons (arns)
+        let parts: Vec<&str> = rest.splitn(3, "::").collect();
+        let [vendor, service, rest] = parts[..] else {
+            return Err(err());
+        };
+        let (resource, identifier) = rest.split_once(':').ok_or_else(err)?;
+
+        if [logical_id, vendor, service, resource, identifier]
+            .iter()
+            .any(|p| p.trim().is_empty())
+        {
+            return Err(err());
+        }
+
+        let identifier = if identifier.contains('=') {
+            identifier
+                .split(',')
+                .map(|p| {
+                    let (k, v) = p.split_once('=').ok_or_else(err)?;
+                    Ok((Some(k.trim().to_string()), v.trim().to_string()))
+                })
+                .collect::<Result<Vec<_>, String>>()?
+        } else {
+            vec![(None, identifier.trim().to_string())]
+        };
+
+        Ok(ResourceSpec {
+            logical_id: logical_id.trim().to_string(),
+            resource_type: format!("{}::{}::{}", vendor, service, resource),
+            identifier,
+        })
+    }
+
+    // resource_identifier maps the identifier of the spec to the
+    // identifier properties cloudformation expects for the type
+    pub fn resource_identifier(&self, keys: &[String]) -> Result<HashMap<String, String>, String> {
+        if keys.is_empty() {
+            return Err(format!(
+                "resource type {} of [{}] doesn't support import",
+                self.resource_type, self.logical_id
+            ));
+        }
+
+        let mut identifier = HashMap::new();
+        for (k, v) in self.identifier.iter() {
+            let key = match k {
+                Some(k) if keys.contains(k) => k.clone(),
+                None if keys.len() == 1 => keys[0].clone(),
+                _ => {
+                    return Err(format!(
+                        "[{}] is identified by {}, use {}",
+                        self.logical_id,
+                        keys.join(", "),
+                        keys.iter()
+                            .map(|k| format!("{}=<value>", k))
+                            .collect::<Vec<String>>()
+                            .join(",")
+                    ))
+                }
+            };
+            identifier.insert(key, v.clone());
+        }
+
+        if let Some(missing) = keys.iter().find(|k| !identifier.contains_key(*k)) {
+            return Err(format!(
+                "[{}] is missing identifier property {}",
+                self.logical_id, missing
+            ));
+        }
+
+        Ok(identifier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn test_adopt_resources() {
+        let spec = ResourceSpec::parse("Bucket=AWS::S3::Bucket:my-bucket").unwrap();
+        assert_eq!(spec.logical_id, "Bucket");
+        assert_eq!(spec.resource_type, "AWS::S3::Bucket");
+        assert_eq!(spec.identifier, vec![(None, "my-bucket".to_string())]);
+
+        // identifiers may contain colons
+        let topic =
+            ResourceSpec::parse("Topic=AWS::SNS::Topic:arn:aws:sns:eu-west-1:123456789012:events")
+                .unwrap();
+        assert_eq!(topic.resource_type, "AWS::SNS::Topic");
+        let identifier = topic
+            .resource_identifier(&["TopicArn".to_string()])
+            .unwrap();
+        assert_eq!(
+            identifier["TopicArn"],
+            "arn:aws:sns:eu-west-1:123456789012:events"
+        );
+
+        let attachment = ResourceSpec::parse(
+            "Attachment=AWS::EC2::VPCGatewayAttachment:VpcId=vpc-1,InternetGatewayId=igw-1",
+        )
+        .unwrap();
+        let keys = ["VpcId".to_string(), "InternetGatewayId".to_string()];
+        let identifier = attachment.resource_identifier(&keys).unwrap();
+        assert_eq!(identifier["VpcId"], "vpc-1");
+        assert_eq!(identifier["InternetGatewayId"], "igw-1");
+
+        // a bare value is ambiguous for types with several identifier properties
+        let bare = ResourceSpec::parse("Attachment=AWS::EC2::VPCGatewayAttachment:vpc-1").unwrap();
+        assert!(bare.resource_identifier(&keys).is_err());
+        assert!(spec.resource_identifier(&[]).is_err());
+
+        for invalid in [
+            "Bucket",
+            "Bucket=AWS::S3::Bucket",
+            "=AWS::S3::Bucket:b",
+            "B=S3:b",
+        ] {
+            assert!(ResourceSpec::parse(invalid).is_err(), "{}", invalid);
+        }
+
+        let parsed = template::parse(indoc! {r#"
+            Resources:
+              Bucket:
+                Type: AWS::S3::Bucket
+                DeletionPolicy: Retain
+              Queue:
+                Type: AWS::SQS::Queue
+        "#})
+        .unwrap();
+
+        assert!(template::check_import(&parsed, "Bucket", "AWS::S3::Bucket").is_ok());
+        assert!(template::check_import(&parsed, "Bucket", "AWS::SQS::Queue").is_err());
+        assert!(template::check_import(&parsed, "Queue", "AWS::SQS::Queue").is_err());
+        assert!(template::check_import(&parsed, "Missing", "AWS::S3::Bucket").is_err());
+    }
+}
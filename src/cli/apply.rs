@@ -1,5 +1,7 @@
 use aws_config::{self, BehaviorVersion};
-use aws_sdk_cloudformation::types::Capability;
+use aws_sdk_cloudformation::types::{
+    Capability, Parameter, ResourceStatus, Stack, StackEvent, StackStatus, Tag,
+};
 
 use aws_types::region::Region;
 use aws_types::SdkConfig;
@@ -7,9 +9,16 @@ use clap::ArgMatches;
 use clap::{arg, Command};
 use colored::Colorize;
 use log;
+use std::collections::BTreeMap;
 use std::env;
+use std::io::IsTerminal;
 
 use crate::config;
+use crate::graph::Graph;
+use crate::output;
+use crate::parameters;
+use crate::plan;
+use crate::status;
 use crate::utils;
 use utils::exec_jobs;
 use utils::stack_request_result_handle;
@@ -18,6 +27,7 @@ use aws_smithy_types::body::SdkBody;
 use aws_smithy_types::byte_stream::ByteStream;
 
 use crate::stacks;
+use crate::template;
 use md5;
 
 const ABOUT: &str = r#"deploy or udpates stacks based on cloudformation template,
@@ -30,6 +40,21 @@ pub fn command() -> Command {
         .alias("a")
         .arg(arg!([stack]))
         .arg(arg!(-A --all ... "apply (update/deply) all stacks"))
+        .arg(arg!(--plan "preview changes with a change set and confirm before applying"))
+        .arg(arg!(-y --yes "execute planned change sets without asking for confirmation"))
+        .arg(arg!(--"allow-replacement" "allow updates that replace protected stateful resources"))
+        .arg(
+            arg!(-p --parallel <N> "number of independent stacks applied at the same time")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .default_value("1"),
+        )
+        .arg(arg!(--"keep-going" "keep applying the other stacks when a stack fails"))
+        .arg(arg!(--recover "recover stacks left in a failed state without asking"))
+        .arg(arg!(--"wait-for-idle" "wait for operations in progress on a stack to finish instead of failing"))
+        .arg(
+            arg!(--"skip-resource" <LOGICAL_ID> "resource skipped when continuing a failed update rollback")
+                .action(clap::ArgAction::Append),
+        )
         .arg(arg!(-c --config <FILE> "path to config file"))
 }
 
@@ -49,7 +74,7 @@ pub async fn handle(matches: &ArgMatches) -> Result<(), String> {
     let conf = config::load_config_from_file(config_path.unwrap())?;
 
     let apply_all = matches.get_one::<u8>("all").unwrap_or(&0);
-    let mut selected_stacks = if *apply_all == 1 {
+    let selected_stacks: Vec<&stacks::Stack> = if *apply_all == 1 {
         conf.stacks.iter().collect()
     } else {
         match matches.get_one::<String>("stack") {
@@ -75,18 +100,6 @@ pub async fn handle(matches: &ArgMatches) -> Result<(), String> {
         }
     };
 
-    selected_stacks.sort_by(|a, b| {
-        if a.is_dependency_of(b) {
-            return std::cmp::Ordering::Less;
-        }
-
-        if b.is_dependency_of(a) {
-            return std::cmp::Ordering::Greater;
-        }
-
-        std::cmp::Ordering::Equal
-    });
-
     if selected_stacks.len() == 0 {
         return Err("no stacks found".to_string());
     }
@@ -99,148 +112,593 @@ pub async fn handle(matches: &ArgMatches) -> Result<(), String> {
             .collect::<Vec<String>>()
     );
 
-    // iterate stacks
-    for stack in selected_stacks.iter() {
-        // // create client per stack
-        let region = stack.region.clone().unwrap_or("eu-west-1".to_string());
-        let sdk_config = aws_config::defaults(BehaviorVersion::latest())
-            .region(Region::new(region))
-            .load()
-            .await;
-        let client = aws_sdk_cloudformation::Client::new(&sdk_config);
+    // prompt for required parameters that are not set in the config
+    let mut selected_stacks: Vec<stacks::Stack> = selected_stacks.into_iter().cloned().collect();
+    for stack in selected_stacks.iter_mut() {
+        prompt_missing_parameters(stack)?;
+    }
 
-        // convert parameters to vec of Parameter
-        let mut params = Vec::new();
-        if let Some(p) = &stack.parameters {
-            p.iter().for_each(|(k, v)| {
-                let param = aws_sdk_cloudformation::types::Parameter::builder()
-                    .parameter_value(v)
-                    .parameter_key(k)
-                    .build();
-                params.push(param);
-            });
-        };
+    // pre-flight: validate the parameters of every selected stack
+    // against its template before making any api calls
+    let issues: Vec<String> = selected_stacks
+        .iter()
+        .filter_map(|s| parameters::validate_stack(s).err())
+        .collect();
+    if !issues.is_empty() {
+        return Err(issues.join("\n"));
+    }
 
-        // get capabilities
-        let capabilities = Some(
-            stack
-                .capabilities
-                .clone()
-                .unwrap_or(vec![])
-                .iter()
-                .map(|c| Capability::from(c.as_str()))
-                .collect(),
+    // note: unwrap is fine here, the flag has a default value
+    let parallel = *matches.get_one::<u64>("parallel").unwrap() as usize;
+    if parallel > 1 && matches.get_flag("plan") {
+        return Err(
+            "--plan can't be combined with --parallel, change sets are confirmed one at a time"
+                .to_string(),
         );
+    }
+    utils::set_concurrent(parallel > 1);
+
+    // stacks start once their dependencies are applied
+    let graph = Graph::new(selected_stacks, false)?;
+    let runs = graph
+        .run(parallel, matches.get_flag("keep-going"), |stack| {
+            let matches = matches.clone();
+            async move { apply_stack(&matches, &stack).await }
+        })
+        .await;
+
+    let mut report = output::Report::new("apply");
+    let mut errors = Vec::new();
+    for run in runs {
+        report.stacks.push(output::StackResult::from_outcome(
+            &run.stack,
+            &run.result,
+            run.started,
+        ));
+
+        if let Err(e) = run.result {
+            errors.push((run.stack.name, e));
+        }
+    }
+
+    // multi-stack runs end with a summary, which lists the errors
+    let result = match errors.len() {
+        0 => Ok(()),
+        _ if report.stacks.len() == 1 => Err(errors.remove(0).1),
+        _ => Err(format!(
+            "stacks failed: {}",
+            errors
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<String>>()
+                .join(", ")
+        )),
+    };
+    if report.stacks.len() > 1 {
+        report.print_summary();
+    }
+    report.emit(&result)?;
+    result
+}
+
+// apply_stack creates or updates a single stack
+async fn apply_stack(
+    matches: &ArgMatches,
+    stack: &stacks::Stack,
+) -> Result<output::Outcome, String> {
+    // // create client per stack
+    let region = stack.region.clone().unwrap_or("eu-west-1".to_string());
+    let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+        .region(Region::new(region))
+        .load()
+        .await;
+    let client = aws_sdk_cloudformation::Client::new(&sdk_config);
+
+    // operations started elsewhere are waited for (with --wait-for-idle),
+    // and stacks left in a failed state are recovered before deploying
+    let mut deployed = utils::describe_stack(&client, &stack.name).await?;
+    if deployed.as_ref().is_some_and(utils::is_busy) {
+        deployed = wait_for_idle(matches, &client, stack).await?;
+    }
+    let deployed = match deployed {
+        Some(d) => recover_stack(matches, &client, stack, d).await?,
+        None => None,
+    };
+
+    // run update if stack exists
+    let exists = deployed.is_some();
+
+    // pre hooks run before the template is rendered and sent, hooks
+    // may write artifacts the stack reads. They also run when the
+    // change set turns out to have nothing to change or is declined
+    if exists {
+        exec_jobs!(on_update, &stack, stack.name.clone(), false);
+    } else {
+        exec_jobs!(on_create, &stack, stack.name.clone(), false);
+    }
+
+    let input = StackInput::new(stack, &sdk_config, deployed.as_ref()).await?;
+
+    // updates (and planned creates) go through a change set, so that
+    // resource replacements can be checked before anything changes
+    let plan_first = matches.get_flag("plan");
+    if exists || plan_first {
+        let Some(cs) = plan::create_change_set(&client, stack, &input, exists).await? else {
+            update_termination_protection(&client, stack).await?;
+            return Ok(output::Outcome::Unchanged);
+        };
+
+        if !matches.get_flag("allow-replacement") {
+            if let Err(e) = plan::check_replacements(stack, &cs) {
+                plan::print_changes(&cs);
+                plan::delete_change_set(&client, &cs).await?;
+                return Err(e);
+            }
+        }
 
-        // run update if stack exists
-        let exists = utils::stack_exists(&client, &stack.name).await;
-        if let Ok(_) = exists {
+        if plan_first {
+            plan::print_changes(&cs);
+            let approved = utils::approve(
+                &format!("[{}] execute change set?", stack.name),
+                matches.get_flag("yes"),
+            );
+            if !matches!(approved, Ok(true)) {
+                plan::delete_change_set(&client, &cs).await?;
+                approved?;
+                log::info!("[{}] {}", stack.name.cyan(), "change set declined".yellow());
+                return Ok(output::Outcome::Declined);
+            }
+        }
+
+        if exists {
             // stack exists, update
-            // execute on_update hooks
-            exec_jobs!(on_update, &stack, stack.name.clone(), false);
-            update_stack(&client, &stack, sdk_config, capabilities, params).await?;
+            // nothing to update, post-update hooks are skipped
+            if !plan::execute_change_set(&client, stack, &cs).await? {
+                update_termination_protection(&client, stack).await?;
+                return Ok(output::Outcome::Unchanged);
+            }
+            update_termination_protection(&client, stack).await?;
             exec_jobs!(on_update, &stack, stack.name.clone(), true);
-            return Ok(());
+            return Ok(output::Outcome::Updated);
         }
 
-        // execute on_apply hook
-        exec_jobs!(on_create, &stack, stack.name.clone(), false);
-        create_stack(&client, &stack, sdk_config, capabilities, params).await?;
+        plan::execute_change_set(&client, stack, &cs).await?;
+        update_termination_protection(&client, stack).await?;
         exec_jobs!(on_create, &stack, stack.name.clone(), true);
+        return Ok(output::Outcome::Created);
     }
 
-    Ok(())
+    create_stack(&client, stack, &input).await?;
+    exec_jobs!(on_create, &stack, stack.name.clone(), true);
+    Ok(output::Outcome::Created)
 }
 
-// update_stack updates a stack
-pub async fn update_stack(
+// wait_for_idle follows the operation in progress on a stack until the stack
+// is stable, when --wait-for-idle is set. Otherwise it fails with the
+// details of the operation. The stack is returned if it still exists
+async fn wait_for_idle(
+    matches: &ArgMatches,
     client: &aws_sdk_cloudformation::Client,
     s: &stacks::Stack,
-    sdk_config: SdkConfig,
-    // stack_name: &str,
-    // template: &str,
-    capabilities: Option<Vec<Capability>>,
-    params: Vec<aws_sdk_cloudformation::types::Parameter>,
-) -> Result<(), String> {
-    log::debug!("update_stack function called for stack: {}", s.name);
-    // load template
-    let template = s.generate_template()?;
+) -> Result<Option<Stack>, String> {
+    if !matches.get_flag("wait-for-idle") {
+        return Err(busy_error(client, s).await);
+    }
+
+    utils::wait_for_idle(
+        client,
+        &s.name,
+        s.region.clone().unwrap_or("eu-west-1".to_string()),
+    )
+    .await
+}
 
-    let mut req = client
-        .update_stack()
+// busy_error describes the operation in progress on a stack, from the
+// stack event that started it
+async fn busy_error(client: &aws_sdk_cloudformation::Client, s: &stacks::Stack) -> String {
+    let events = client
+        .describe_stack_events()
         .stack_name(&s.name)
-        .set_parameters(Some(params))
-        .set_capabilities(capabilities);
+        .send()
+        .await
+        .map(|r| r.stack_events().to_vec())
+        .unwrap_or_default();
 
-    // check if template is more than 52,000 bytes
-    req = if template.as_bytes().len() > 51200 {
-        let bucket = s.bucket.as_ref().ok_or_else(|| {
-            format!(
-                "[{}] error: no bucket defined for large template (>51200 bytes)",
-                s.name
-            )
-        })?;
-        let key = format!("kloi-{}", &s.template.md5());
-        let url = s3upload(sdk_config, bucket.clone(), key, template.to_string()).await?;
+    busy_message(&s.name, &events)
+}
 
-        req.template_url(&url)
-    } else {
-        req.template_body(&template)
+// busy_message describes the operation in progress on a stack from its
+// events, listed newest first
+fn busy_message(stack_name: &str, events: &[StackEvent]) -> String {
+    let started = events.iter().find(|e| {
+        e.logical_resource_id() == Some(stack_name)
+            && e.resource_type() == Some("AWS::CloudFormation::Stack")
+            && e.resource_status()
+                .is_some_and(|r| r.as_str().ends_with("_IN_PROGRESS"))
+    });
+
+    let Some(e) = started else {
+        return format!(
+            "[{}] another operation is in progress on the stack, run apply with --wait-for-idle to wait for it",
+            stack_name
+        );
     };
 
-    let res = req.send().await;
+    let mut details = Vec::new();
+    if e.timestamp().is_some() {
+        details.push(format!("started at {}", status::format_time(e.timestamp())));
+    }
+    if let Some(token) = e.client_request_token() {
+        details.push(format!("request token {}", token));
+    }
+    if let Some(reason) = e.resource_status_reason() {
+        details.push(format!("reason: {}", reason));
+    }
 
-    stack_request_result_handle!(res, s.name, "update stack");
+    format!(
+        "[{}] stack is in {} state ({}), run apply with --wait-for-idle to wait for it",
+        stack_name,
+        e.resource_status().map(|r| r.as_str()).unwrap_or("-"),
+        details.join(", ")
+    )
+}
 
-    // wait for stack
+// Recovery is how a stack left in a failed state is made deployable again
+#[derive(Debug, PartialEq)]
+enum Recovery {
+    Delete,
+    ContinueRollback,
+}
+
+impl Recovery {
+    // of returns the recovery a stack status needs, if any
+    fn of(status: &StackStatus) -> Option<Recovery> {
+        match status {
+            // the stack never succeeded, it can only be deleted
+            StackStatus::RollbackComplete | StackStatus::RollbackFailed => Some(Recovery::Delete),
+            StackStatus::UpdateRollbackFailed => Some(Recovery::ContinueRollback),
+            _ => None,
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Recovery::Delete => "delete it and create it again",
+            Recovery::ContinueRollback => "continue the update rollback",
+        }
+    }
+}
+
+// recover_stack brings a stack left in a failed state back to a state that
+// can be deployed, after confirmation or with --recover. Stacks that failed
+// to create are deleted, failed update rollbacks are continued. The stack
+// is returned if it still exists
+async fn recover_stack(
+    matches: &ArgMatches,
+    client: &aws_sdk_cloudformation::Client,
+    s: &stacks::Stack,
+    deployed: Stack,
+) -> Result<Option<Stack>, String> {
+    let Some(status) = deployed.stack_status().cloned() else {
+        return Ok(Some(deployed));
+    };
+    let Some(recovery) = Recovery::of(&status) else {
+        return Ok(Some(deployed));
+    };
+    let fix = recovery.describe();
+
+    log::warn!(
+        "[{}] {} {}: {}",
+        s.name.cyan(),
+        "stack is in".yellow(),
+        status.as_str().red(),
+        deployed.stack_status_reason().unwrap_or("no reason given")
+    );
+
+    let interactive = std::io::stdin().is_terminal()
+        && std::io::stdout().is_terminal()
+        && !utils::is_concurrent();
+    let recover = matches.get_flag("recover")
+        || (interactive && utils::confirm(&format!("[{}] {}?", s.name, fix)));
+    if !recover {
+        return Err(format!(
+            "[{}] stack is in {} state, run apply with --recover to {}",
+            s.name,
+            status.as_str(),
+            fix
+        ));
+    }
+
+    if recovery == Recovery::ContinueRollback {
+        continue_update_rollback(matches, client, s, interactive).await?;
+        return utils::describe_stack(client, &s.name).await;
+    }
+
+    let token = utils::request_token();
+    let res = client
+        .delete_stack()
+        .stack_name(&s.name)
+        .client_request_token(&token)
+        .send()
+        .await;
+    stack_request_result_handle!(res, s.name, "delete stack");
     utils::stackprogress(
-        &client,
+        client,
         &s.name,
-        s.custom_resources.clone(),
-        s.region.clone().unwrap(),
-        utils::WaitEvent::Update,
+        None,
+        s.region.clone().unwrap_or("eu-west-1".to_string()),
+        utils::WaitEvent::Delete,
+        Some(&token),
     )
-    .await
-    // utils::wait_for_stack_v2(&client, &s.name, utils::WaitEvent::Update).await
+    .await?;
+
+    match utils::describe_stack(client, &s.name).await? {
+        Some(d) => Err(format!(
+            "[{}] stack could not be deleted, it is in {} state",
+            s.name,
+            d.stack_status().map(|s| s.as_str()).unwrap_or("unknown")
+        )),
+        None => Ok(None),
+    }
 }
 
-// create_stack creates a stack
-pub async fn create_stack(
+// continue_update_rollback continues the rollback of a failed update.
+// Resources that can't be rolled back are skipped, they are given with
+// --skip-resource or picked from the failed resources interactively
+async fn continue_update_rollback(
+    matches: &ArgMatches,
     client: &aws_sdk_cloudformation::Client,
     s: &stacks::Stack,
-    sdk_config: SdkConfig,
-    // stack_name: &str,
-    // template: &str,
-    capabilities: Option<Vec<Capability>>,
-    params: Vec<aws_sdk_cloudformation::types::Parameter>,
+    interactive: bool,
 ) -> Result<(), String> {
-    // load template
-    log::debug!("create_stack function called for stack: {}", s.name);
-    let template = s.generate_template()?;
+    let mut skip: Vec<String> = matches
+        .get_many::<String>("skip-resource")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+
+    if skip.is_empty() && interactive && !matches.get_flag("recover") {
+        let failed: Vec<String> = client
+            .describe_stack_resources()
+            .stack_name(&s.name)
+            .send()
+            .await
+            .map_err(|e| {
+                format!(
+                    "[{}] error describing stack resources: {}",
+                    s.name,
+                    e.into_service_error()
+                )
+            })?
+            .stack_resources()
+            .iter()
+            .filter(|r| r.resource_status() == Some(&ResourceStatus::UpdateFailed))
+            .filter_map(|r| r.logical_resource_id().map(|id| id.to_string()))
+            .collect();
 
-    let mut req = client
-        .create_stack()
+        if !failed.is_empty() {
+            skip = utils::multiselect(failed, "select resources to skip (none to retry them)");
+        }
+    }
+
+    let token = utils::request_token();
+    let res = client
+        .continue_update_rollback()
         .stack_name(&s.name)
-        .set_parameters(Some(params))
-        .set_capabilities(capabilities);
+        .set_resources_to_skip(Some(skip))
+        .set_role_arn(s.role_arn.clone())
+        .client_request_token(&token)
+        .send()
+        .await;
+    stack_request_result_handle!(res, s.name, "continue update rollback");
 
-    // check if template is more than 52,000 bytes
-    req = if template.as_bytes().len() > 51200 {
-        let bucket = s.bucket.as_ref().ok_or_else(|| {
-            format!(
-                "[{}] error: no bucket defined for large template (>51200 bytes)",
-                s.name
-            )
-        })?;
-        let key = format!("kloi-{}", &s.template.md5());
-        let url = s3upload(sdk_config, bucket.clone(), key, template.to_string()).await?;
+    utils::watch_stack(
+        client,
+        &s.name,
+        None,
+        s.region.clone().unwrap_or("eu-west-1".to_string()),
+        utils::WaitEvent::Rollback,
+        Some(&token),
+    )
+    .await?;
+
+    let status = utils::describe_stack(client, &s.name)
+        .await?
+        .and_then(|d| d.stack_status().cloned());
+    if status != Some(StackStatus::UpdateRollbackComplete) {
+        return Err(format!(
+            "[{}] update rollback did not complete, stack is in {} state",
+            s.name,
+            status
+                .map(|s| s.as_str().to_string())
+                .unwrap_or("unknown".to_string())
+        ));
+    }
 
-        req.template_url(&url)
-    } else {
-        req.template_body(&template)
+    Ok(())
+}
+
+// prompt_missing_parameters asks for the value of every required template
+// parameter the stack does not set. Non-interactive runs fail instead
+pub fn prompt_missing_parameters(stack: &mut stacks::Stack) -> Result<(), String> {
+    let rendered = stack.generate_template()?;
+    let Ok(t) = template::parse(&rendered) else {
+        // unparsable templates are reported by the parameter validation
+        return Ok(());
     };
 
-    let res = req.send().await;
+    let declared = template::parameters(&t);
+    let mut params = stack.parameters.clone().unwrap_or_default();
+    let missing = parameters::missing(
+        &params,
+        &stack.previous_parameters.clone().unwrap_or_default(),
+        &declared,
+    );
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        return Err(format!(
+            "[{}] missing required parameters: {}. Set them in the config or run apply interactively to be prompted",
+            stack.name,
+            missing.join(", ")
+        ));
+    }
+
+    let prompt = format!(
+        "[{}] missing required parameters: {}. Enter them now?",
+        stack.name,
+        missing.join(", ")
+    );
+    if !utils::confirm(&prompt) {
+        return Err(format!(
+            "[{}] missing required parameters: {}",
+            stack.name,
+            missing.join(", ")
+        ));
+    }
+
+    for name in missing.iter() {
+        let value = utils::prompt_parameter(name, &declared[name])?;
+        params.insert(name.clone(), serde_json::Value::String(value));
+    }
+
+    stack.parameters = Some(params);
+    Ok(())
+}
+
+// StackInput holds the request inputs shared by
+// stack create, update and change set requests
+pub struct StackInput {
+    // rendered template
+    pub template: String,
+    // s3 url of templates too large to send in the request
+    pub template_url: Option<String>,
+    pub parameters: Vec<Parameter>,
+    pub capabilities: Vec<Capability>,
+    pub tags: Vec<Tag>,
+}
+
+impl StackInput {
+    // new builds the request inputs of a stack, uploading the template
+    // to the stack bucket when it exceeds the request size limit.
+    // deployed is the stack as it exists in cloudformation, if it does
+    pub async fn new(
+        s: &stacks::Stack,
+        sdk_config: &SdkConfig,
+        deployed: Option<&Stack>,
+    ) -> Result<Self, String> {
+        let exists = deployed.is_some();
+
+        // load template
+        let template = s.generate_template()?;
+
+        // convert parameters to vec of Parameter
+        let mut params = Vec::new();
+        if let Some(p) = &s.parameters {
+            let p = parameters::resolve(p, &template).map_err(|e| format!("[{}] {}", s.name, e))?;
+            p.iter().for_each(|(k, v)| {
+                let param = Parameter::builder()
+                    .parameter_value(v)
+                    .parameter_key(k)
+                    .build();
+                params.push(param);
+            });
+        };
+
+        // keep the deployed values of parameters marked with UsePreviousValue
+        for k in s.previous_parameters.iter().flatten() {
+            if !exists {
+                log::warn!(
+                    "[{}] ignoring UsePreviousValue for parameter [{}] on stack create",
+                    s.name.cyan(),
+                    k
+                );
+                continue;
+            }
+
+            let param = Parameter::builder()
+                .parameter_key(k)
+                .use_previous_value(true)
+                .build();
+            params.push(param);
+        }
+
+        // get capabilities
+        let capabilities = s
+            .capabilities
+            .clone()
+            .unwrap_or_default()
+            .iter()
+            .map(|c| Capability::from(c.as_str()))
+            .collect();
+
+        // check if template is more than 51,200 bytes
+        let template_url = if template.len() > 51200 {
+            let bucket = s.bucket.as_ref().ok_or_else(|| {
+                format!(
+                    "[{}] error: no bucket defined for large template (>51200 bytes)",
+                    s.name
+                )
+            })?;
+            let key = format!("kloi-{}", &s.template.md5());
+            Some(
+                s3upload(
+                    sdk_config.clone(),
+                    bucket.clone(),
+                    key,
+                    template.to_string(),
+                )
+                .await?,
+            )
+        } else {
+            None
+        };
+
+        Ok(StackInput {
+            template,
+            template_url,
+            parameters: params,
+            capabilities,
+            // tags set on the deployed stack outside kloi are kept
+            tags: stack_tags(s, deployed.map(|d| d.tags()).unwrap_or_default()),
+        })
+    }
+
+    // template_body returns the template to send in the
+    // request body, if it was not uploaded to s3
+    pub fn template_body(&self) -> Option<String> {
+        match self.template_url {
+            Some(_) => None,
+            None => Some(self.template.clone()),
+        }
+    }
+}
+
+// create_stack creates a stack
+pub async fn create_stack(
+    client: &aws_sdk_cloudformation::Client,
+    s: &stacks::Stack,
+    input: &StackInput,
+) -> Result<(), String> {
+    log::debug!("create_stack function called for stack: {}", s.name);
+    let token = utils::request_token();
+    let res = client
+        .create_stack()
+        .stack_name(&s.name)
+        .set_template_body(input.template_body())
+        .set_template_url(input.template_url.clone())
+        .set_parameters(Some(input.parameters.clone()))
+        .set_capabilities(Some(input.capabilities.clone()))
+        .set_tags(Some(input.tags.clone()))
+        .set_role_arn(s.role_arn.clone())
+        .set_notification_arns(s.notification_arns.clone())
+        .set_enable_termination_protection(s.termination_protection)
+        .client_request_token(&token)
+        .send()
+        .await;
 
     stack_request_result_handle!(res, s.name, "create stack");
 
@@ -251,11 +709,55 @@ pub async fn create_stack(
         s.custom_resources.clone(),
         s.region.clone().unwrap(),
         utils::WaitEvent::Create,
+        Some(&token),
     )
     .await
     // utils::wait_for_stack_v2(&client, &s.name, utils::WaitEvent::Create).await
 }
 
+// update_termination_protection sets the termination protection of
+// a deployed stack, if the stack config sets it
+pub async fn update_termination_protection(
+    client: &aws_sdk_cloudformation::Client,
+    s: &stacks::Stack,
+) -> Result<(), String> {
+    let Some(enabled) = s.termination_protection else {
+        return Ok(());
+    };
+
+    client
+        .update_termination_protection()
+        .stack_name(&s.name)
+        .enable_termination_protection(enabled)
+        .send()
+        .await
+        .map_err(|e| {
+            format!(
+                "[{}] error updating termination protection: {}",
+                s.name,
+                e.into_service_error()
+            )
+        })?;
+
+    Ok(())
+}
+
+// stack_tags converts the stack tags to cloudformation tags. Requests
+// replace the whole tag set of a stack, so tags set on the deployed stack
+// outside kloi are merged in. The kloi: tags always follow the config
+fn stack_tags(s: &stacks::Stack, deployed: &[Tag]) -> Vec<Tag> {
+    let mut tags: BTreeMap<String, String> = deployed
+        .iter()
+        .filter_map(|t| Some((t.key()?.to_string(), t.value()?.to_string())))
+        .filter(|(k, _)| !k.starts_with("kloi:"))
+        .collect();
+    tags.extend(s.tags());
+
+    tags.iter()
+        .map(|(k, v)| Tag::builder().key(k).value(v).build())
+        .collect()
+}
+
 async fn s3upload(
     sdk_config: SdkConfig,
     bucket: String,
@@ -280,7 +782,7 @@ async fn s3upload(
 }
 
 // Md5Sum is a trait for generating md5 hash of a string
-trait Md5Sum {
+pub trait Md5Sum {
     fn md5(&self) -> String;
 }
 
@@ -291,3 +793,101 @@ impl Md5Sum for String {
         format!("{:x}", digest)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+
+    fn load_stack(config: &str) -> stacks::Stack {
+        config::load_test_config(config).stacks.remove(0)
+    }
+
+    #[test]
+    fn test_busy_message() {
+        let event = |id: &str, status: ResourceStatus| {
+            StackEvent::builder()
+                .logical_resource_id(id)
+                .resource_type(match id {
+                    "app" => "AWS::CloudFormation::Stack",
+                    _ => "AWS::SNS::Topic",
+                })
+                .resource_status(status)
+                .client_request_token("kloi-1")
+                .resource_status_reason("User Initiated")
+                .build()
+        };
+
+        // the newest in progress event of the stack itself is described
+        let events = vec![
+            event("Topic", ResourceStatus::UpdateInProgress),
+            event("app", ResourceStatus::UpdateInProgress),
+            event("app", ResourceStatus::CreateComplete),
+        ];
+        assert_eq!(
+            busy_message("app", &events),
+            "[app] stack is in UPDATE_IN_PROGRESS state (request token kloi-1, reason: User Initiated), run apply with --wait-for-idle to wait for it"
+        );
+
+        assert_eq!(
+            busy_message("app", &events[..1]),
+            "[app] another operation is in progress on the stack, run apply with --wait-for-idle to wait for it"
+        );
+    }
+
+    #[test]
+    fn test_recovery() {
+        let cases = [
+            (StackStatus::RollbackComplete, Some(Recovery::Delete)),
+            (StackStatus::RollbackFailed, Some(Recovery::Delete)),
+            (
+                StackStatus::UpdateRollbackFailed,
+                Some(Recovery::ContinueRollback),
+            ),
+            (StackStatus::UpdateRollbackComplete, None),
+            (StackStatus::CreateComplete, None),
+            (StackStatus::UpdateInProgress, None),
+        ];
+        for (status, recovery) in cases {
+            assert_eq!(Recovery::of(&status), recovery, "{}", status.as_str());
+        }
+        assert_eq!(
+            Recovery::ContinueRollback.describe(),
+            "continue the update rollback"
+        );
+    }
+
+    #[test]
+    fn test_stack_tags() {
+        let stack = load_stack(
+            r#"stacks.add(stacks.new(name = "app", region = "eu-west-1", template = "none", options = stacks.options(tags = {"team": "platform"})))"#,
+        );
+        let tag = |k: &str, v: &str| Tag::builder().key(k).value(v).build();
+        let deployed = vec![
+            tag("cost-center", "42"),
+            tag("team", "legacy"),
+            tag(stacks::TTL_TAG, "1h"),
+        ];
+
+        let tags: Vec<(String, String)> = stack_tags(&stack, &deployed)
+            .iter()
+            .map(|t| {
+                (
+                    t.key().unwrap_or_default().to_string(),
+                    t.value().unwrap_or_default().to_string(),
+                )
+            })
+            .collect();
+
+        // tags set outside kloi are kept, the config wins and the
+        // ttl removed from the config is not carried over
+        assert_eq!(
+            tags,
+            vec![
+                ("cost-center".to_string(), "42".to_string()),
+                (stacks::MANAGED_TAG.to_string(), "true".to_string()),
+                ("team".to_string(), "platform".to_string()),
+            ]
+        );
+    }
+}
@@ -1,4 +1,7 @@
 use crate::config;
+use crate::output::{self, human};
+use crate::parameters;
+use crate::stacks;
 use aws_config::{self, BehaviorVersion};
 use aws_sdk_cloudformation::error::SdkError;
 use aws_types::region::Region;
@@ -11,7 +14,7 @@ use tempdir::TempDir;
 use std::fs::File;
 use std::io::Write;
 
-const ABOUT: &str = r#"validate cloudformation template
+const ABOUT: &str = r#"validate cloudformation template and stack parameters
 This command uses cfn-lint if present on the host, else it will use the AWS Cloudformation validation API
 "#;
 
@@ -55,12 +58,48 @@ pub async fn handle(matches: &ArgMatches) -> Result<(), String> {
 
     // can be unwrapped because we already checked that the stack exists
     let stack = conf.stacks.iter().find(|s| &s.name == &stack_name).unwrap();
+    let result = check_stack(stack).await;
+
+    if output::is_text() {
+        if let Ok(res) = &result {
+            println!("{}", res);
+        }
+        return result.map(|_| ());
+    }
+
+    let mut report = output::Report::new("check");
+    let (outcome, issues) = match &result {
+        Ok(_) => (output::Outcome::Valid, vec![]),
+        Err(e) => (
+            output::Outcome::Invalid,
+            e.lines()
+                .map(|l| l.trim().trim_start_matches("- ").to_string())
+                .filter(|l| !l.is_empty() && l != "---")
+                .collect(),
+        ),
+    };
+    report.stacks.push(output::StackResult {
+        name: stack.name.clone(),
+        region: stack.region.clone().unwrap_or("eu-west-1".to_string()),
+        result: Some(outcome),
+        issues: Some(issues),
+        ..Default::default()
+    });
+    report.emit(&result.map(|_| ()))
+}
+
+// check_stack validates the stack parameters and template,
+// returns the report to print if the stack is valid
+async fn check_stack(stack: &stacks::Stack) -> Result<String, String> {
     let template = stack.generate_template()?;
 
+    // validate parameters against the template parameters section
+    parameters::validate_stack(stack)?;
+    log::info!("[{}] {} parameters valid", stack.name.cyan(), "✔︎".green());
+
     if let Ok(res) = call_cfn_lint(template.clone()) {
         if res.contains("no issues found") {
-            println!("{}", res);
-            return Ok(());
+            return Ok(res);
         }
         // return Ok result as error to retain err code
         // on bad cfn-lint output
@@ -83,18 +122,14 @@ pub async fn handle(matches: &ArgMatches) -> Result<(), String> {
         .await;
 
     match res {
-        Ok(_) => {
-            println!(
-                "{}\n---\n{} no issues found",
-                template.truecolor(96, 96, 96),
-                "✔︎".green()
-            );
-
-            Ok(())
-        }
+        Ok(_) => Ok(format!(
+            "{}\n---\n{} no issues found",
+            template.truecolor(96, 96, 96),
+            "✔︎".green()
+        )),
         Err(e) => match e {
             SdkError::ServiceError(sdk_err) => {
-                println!("{}", template.truecolor(96, 96, 96));
+                human!("{}", template.truecolor(96, 96, 96));
                 let err = format!(
                     "error occured while validating template: {}",
                     sdk_err.into_err().meta().message().unwrap_or("unknown error")
@@ -7,6 +7,9 @@ use log;
 use std::env;
 
 use crate::config;
+use crate::graph::Graph;
+use crate::output;
+use crate::stacks;
 use crate::utils;
 use crate::utils::stack_request_result_handle;
 use utils::exec_jobs;
@@ -20,6 +23,12 @@ pub fn command() -> Command {
         .alias("d")
         .arg(arg!([stack]))
         .arg(arg!(-A --all ... "delete all stacks"))
+        .arg(
+            arg!(-p --parallel <N> "number of independent stacks deleted at the same time")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .default_value("1"),
+        )
+        .arg(arg!(--"keep-going" "keep deleting the other stacks when a stack fails"))
         .arg(arg!(-c --config <FILE> "path to config file"))
 }
 
@@ -39,7 +48,7 @@ pub async fn handle(matches: &ArgMatches) -> Result<(), String> {
     let conf = config::load_config_from_file(config_path.unwrap())?;
 
     let apply_all = matches.get_one::<u8>("all").unwrap_or(&0);
-    let mut selected_stacks = if *apply_all == 1 {
+    let selected_stacks: Vec<&stacks::Stack> = if *apply_all == 1 {
         conf.stacks.iter().collect()
     } else {
         match matches.get_one::<String>("stack") {
@@ -65,21 +74,6 @@ pub async fn handle(matches: &ArgMatches) -> Result<(), String> {
         }
     };
 
-    selected_stacks.sort_by(|a, b| {
-        if a.is_dependency_of(b) {
-            return std::cmp::Ordering::Less;
-        }
-
-        if b.is_dependency_of(a) {
-            return std::cmp::Ordering::Greater;
-        }
-
-        std::cmp::Ordering::Equal
-    });
-
-    // reverse the order of execution_stacks
-    selected_stacks.reverse();
-
     log::debug!(
         "selected stacks: {:?}",
         selected_stacks
@@ -88,40 +82,91 @@ pub async fn handle(matches: &ArgMatches) -> Result<(), String> {
             .collect::<Vec<String>>()
     );
 
-    for stack in selected_stacks {
-        // execute on_delete hook
-        exec_jobs!(on_delete, &stack, stack.name.clone(), false);
-
-        // // create client per stack
-        let region = stack.region.clone().unwrap_or("eu-west-1".to_string());
-        let sdk_config = aws_config::defaults(BehaviorVersion::latest())
-            .region(Region::new(region))
-            .load()
-            .await;
-        let client = aws_sdk_cloudformation::Client::new(&sdk_config);
-
-        // delete stack
-        let res = client
-            .delete_stack()
-            .stack_name(stack.name.clone())
-            .send()
-            .await;
-
-        stack_request_result_handle!(res, stack.name, "delete stack");
-
-        // wait for stack to be deleted
-        utils::stackprogress(
-            &client,
-            &stack.name,
-            stack.custom_resources.clone(),
-            stack.region.clone().unwrap(),
-            utils::WaitEvent::Delete,
+    // note: unwrap is fine here, the flag has a default value
+    let parallel = *matches.get_one::<u64>("parallel").unwrap() as usize;
+    utils::set_concurrent(parallel > 1);
+
+    // stacks are deleted once the stacks depending on them are gone
+    let graph = Graph::new(selected_stacks.into_iter().cloned().collect(), true)?;
+    let runs = graph
+        .run(
+            parallel,
+            matches.get_flag("keep-going"),
+            |stack| async move { delete_stack(&stack).await },
         )
-        .await?;
+        .await;
+
+    let mut report = output::Report::new("delete");
+    let mut errors = Vec::new();
+    for run in runs {
+        report.stacks.push(output::StackResult::from_outcome(
+            &run.stack,
+            &run.result,
+            run.started,
+        ));
+
+        if let Err(e) = run.result {
+            errors.push((run.stack.name, e));
+        }
+    }
 
-        // execute on_deleted hook
-        exec_jobs!(on_delete, &stack, stack.name.clone(), true);
+    // multi-stack runs end with a summary, which lists the errors
+    let result = match errors.len() {
+        0 => Ok(()),
+        _ if report.stacks.len() == 1 => Err(errors.remove(0).1),
+        _ => Err(format!(
+            "stacks failed: {}",
+            errors
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<String>>()
+                .join(", ")
+        )),
+    };
+    if report.stacks.len() > 1 {
+        report.print_summary();
     }
+    report.emit(&result)?;
+    result
+}
 
-    Ok(())
+// delete_stack deletes a single stack and waits for the deletion to finish
+async fn delete_stack(stack: &stacks::Stack) -> Result<output::Outcome, String> {
+    // execute on_delete hook
+    exec_jobs!(on_delete, &stack, stack.name.clone(), false);
+
+    // // create client per stack
+    let region = stack.region.clone().unwrap_or("eu-west-1".to_string());
+    let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+        .region(Region::new(region))
+        .load()
+        .await;
+    let client = aws_sdk_cloudformation::Client::new(&sdk_config);
+
+    // delete stack
+    let token = utils::request_token();
+    let res = client
+        .delete_stack()
+        .stack_name(stack.name.clone())
+        .client_request_token(&token)
+        .send()
+        .await;
+
+    stack_request_result_handle!(res, stack.name, "delete stack");
+
+    // wait for stack to be deleted
+    utils::stackprogress(
+        &client,
+        &stack.name,
+        stack.custom_resources.clone(),
+        stack.region.clone().unwrap(),
+        utils::WaitEvent::Delete,
+        Some(&token),
+    )
+    .await?;
+
+    // execute on_deleted hook
+    exec_jobs!(on_delete, &stack, stack.name.clone(), true);
+
+    Ok(output::Outcome::Deleted)
 }
@@ -0,0 +1,473 @@
+use aws_config::{self, BehaviorVersion};
+use aws_sdk_cloudformation::types::TemplateStage;
+use aws_sdk_cloudformation::Client;
+use aws_types::region::Region;
+use clap::ArgMatches;
+use clap::{arg, Command};
+use colored::Colorize;
+use log;
+use serde_json::Value;
+use similar::TextDiff;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use tempdir::TempDir;
+
+use crate::config;
+use crate::output::{self, human};
+use crate::parameters;
+use crate::stacks;
+use crate::template::{self, DiffAction};
+use crate::utils;
+
+const ABOUT: &str = r#"compare deployed stacks with the locally rendered templates and parameters,
+or with the config at a git revision using --against.
+exits with code 2 if there are differences and 1 on errors
+"#;
+
+// value cloudformation returns for NoEcho parameters
+const MASKED: &str = "****";
+
+pub fn command() -> Command {
+    Command::new("diff")
+        .about(ABOUT.truecolor(125, 174, 189).to_string())
+        .arg(arg!([stack]))
+        .arg(arg!(-A --all ... "diff all stacks"))
+        .arg(arg!(--against <REF> "compare resources with the config at a git revision, without calling aws"))
+        .arg(arg!(-c --config <FILE> "path to config file"))
+}
+
+pub async fn handle(matches: &ArgMatches) -> Result<(), String> {
+    let mut config_path = env::var("KLOI_CONFIG").ok();
+
+    // if config is not set by env, check if it is set by cli
+    if config_path.is_none() {
+        log::debug!("config path is not set by env, KLOI_CONFIG, check CLI -c/--config");
+        config_path = Some(matches
+            .get_one::<String>("config")
+            .ok_or_else(|| "config file required, please supply using -c/--config or set the KLOI_CONFIG env var".to_string())?.to_string());
+    };
+
+    // load config and create client
+    // note: unwrap is fine here, since we've already checked if config is set above
+    let config_path = config_path.unwrap();
+    let conf = config::load_config_from_file(config_path.clone())?;
+
+    let diff_all = matches.get_one::<u8>("all").unwrap_or(&0);
+    let selected_stacks: Vec<&stacks::Stack> = if *diff_all == 1 {
+        conf.stacks.iter().collect()
+    } else {
+        match matches.get_one::<String>("stack") {
+            Some(c) => {
+                let stack = conf
+                    .stacks
+                    .iter()
+                    .find(|s| &s.name == c)
+                    .ok_or_else(|| format!("stack [{}] not found", c))?;
+                vec![stack]
+            }
+            None => {
+                // if no stack is specified, use interactive form
+                let opts = conf
+                    .stacks
+                    .iter()
+                    .map(|s| s.name.clone())
+                    .collect::<Vec<String>>();
+                let selected = utils::multiselect(opts, "select stack");
+                conf.stacks
+                    .iter()
+                    .filter(|s| selected.contains(&s.name))
+                    .collect()
+            }
+        }
+    };
+
+    if selected_stacks.is_empty() {
+        return Err("no stacks found".to_string());
+    }
+
+    let changed = match matches.get_one::<String>("against") {
+        Some(git_ref) => {
+            let old_conf = load_config_at(&config_path, git_ref)?;
+            let mut changed = diff_revision(&old_conf, &selected_stacks, git_ref)?;
+
+            // with --all, also report the stacks removed since the revision
+            if *diff_all == 1 {
+                for old in old_conf
+                    .stacks
+                    .iter()
+                    .filter(|o| !conf.stacks.iter().any(|s| s.name == o.name))
+                {
+                    print_resource_diffs(&old.name, git_ref, Some(old), None)?;
+                    changed.push(old.name.clone());
+                }
+            }
+            changed
+        }
+        None => diff_deployed(&selected_stacks).await?,
+    };
+
+    if !changed.is_empty() {
+        output::differences(
+            "diff",
+            &format!("differences found in stacks: {}", changed.join(", ")),
+        );
+    }
+
+    Ok(())
+}
+
+// diff_deployed diffs the stacks against the deployed stacks,
+// returns the names of the stacks that differ
+async fn diff_deployed(selected_stacks: &[&stacks::Stack]) -> Result<Vec<String>, String> {
+    let mut changed = Vec::new();
+    for stack in selected_stacks {
+        // create client per stack
+        let region = stack.region.clone().unwrap_or("eu-west-1".to_string());
+        let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+            .region(Region::new(region))
+            .load()
+            .await;
+        let client = aws_sdk_cloudformation::Client::new(&sdk_config);
+
+        if diff_stack(&client, stack).await? {
+            changed.push(stack.name.clone());
+        } else {
+            log::info!("[{}] {}", stack.name.cyan(), "no differences".green());
+        }
+    }
+
+    Ok(changed)
+}
+
+// diff_stack prints the differences between the deployed stack and
+// the local config, returns true if there are any
+async fn diff_stack(client: &Client, s: &stacks::Stack) -> Result<bool, String> {
+    let rendered = s.generate_template()?;
+
+    let (deployed_template, deployed_params) = if utils::stack_exists(client, &s.name).await.is_ok()
+    {
+        (
+            deployed_template(client, &s.name).await?,
+            deployed_parameters(client, &s.name).await?,
+        )
+    } else {
+        log::warn!("[{}] stack is not deployed", s.name.cyan());
+        (String::new(), BTreeMap::new())
+    };
+
+    let local_params = local_parameters(s, &rendered, &deployed_params)?;
+
+    let template_changed = print_diff(
+        &format!("{}/template", s.name),
+        &normalize(&deployed_template),
+        &normalize(&rendered),
+    );
+
+    let params_changed = print_diff(
+        &format!("{}/parameters", s.name),
+        &render_parameters(&deployed_params),
+        &render_parameters(&local_params),
+    );
+
+    Ok(template_changed || params_changed)
+}
+
+async fn deployed_template(client: &Client, stack_name: &str) -> Result<String, String> {
+    let res = client
+        .get_template()
+        .stack_name(stack_name)
+        .template_stage(TemplateStage::Original)
+        .send()
+        .await
+        .map_err(|e| format!("error getting template: {}", e.into_service_error()))?;
+
+    Ok(res.template_body().unwrap_or_default().to_string())
+}
+
+async fn deployed_parameters(
+    client: &Client,
+    stack_name: &str,
+) -> Result<BTreeMap<String, String>, String> {
+    let res = client
+        .describe_stacks()
+        .stack_name(stack_name)
+        .send()
+        .await
+        .map_err(|e| format!("error describing stack: {}", e.into_service_error()))?;
+
+    Ok(res
+        .stacks()
+        .first()
+        .map(|s| s.parameters())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|p| {
+            Some((
+                p.parameter_key()?.to_string(),
+                p.parameter_value().unwrap_or_default().to_string(),
+            ))
+        })
+        .collect())
+}
+
+// local_parameters returns the parameter values the stack would be
+// deployed with: template defaults overridden by the stack parameters.
+// Parameters using the previous value keep the deployed value and
+// NoEcho parameters are masked the same way cloudformation masks them
+fn local_parameters(
+    s: &stacks::Stack,
+    rendered: &str,
+    deployed: &BTreeMap<String, String>,
+) -> Result<BTreeMap<String, String>, String> {
+    let declared = template::parse(rendered)
+        .map(|t| template::parameters(&t))
+        .unwrap_or_default();
+
+    let mut params: BTreeMap<String, String> = declared
+        .iter()
+        .filter_map(|(k, p)| Some((k.clone(), p.default.clone()?)))
+        .collect();
+
+    params.extend(parameters::resolve(
+        &s.parameters.clone().unwrap_or_default(),
+        rendered,
+    )?);
+
+    for key in s.previous_parameters.clone().unwrap_or_default() {
+        if let Some(v) = deployed.get(&key) {
+            params.insert(key, v.clone());
+        }
+    }
+
+    for (k, v) in params.iter_mut() {
+        if declared.get(k).is_some_and(|p| p.no_echo) {
+            *v = MASKED.to_string();
+        }
+    }
+
+    Ok(params)
+}
+
+// normalize renders templates in a format and key order insensitive
+// form, templates that can't be parsed are compared as they are
+fn normalize(template: &str) -> String {
+    if template.is_empty() {
+        return String::new();
+    }
+
+    template::normalize(template).unwrap_or_else(|e| {
+        log::debug!("comparing template as text: {}", e);
+        template.to_string()
+    })
+}
+
+fn render_parameters(params: &BTreeMap<String, String>) -> String {
+    params
+        .iter()
+        .map(|(k, v)| format!("{}: {}\n", k, v))
+        .collect()
+}
+
+// print_diff prints a colored unified diff of the deployed and local
+// versions, returns true if they differ
+fn print_diff(name: &str, deployed: &str, local: &str) -> bool {
+    if deployed == local {
+        return false;
+    }
+
+    let diff = TextDiff::from_lines(deployed, local);
+    let unified = diff
+        .unified_diff()
+        .context_radius(3)
+        .header(
+            &format!("{} (deployed)", name),
+            &format!("{} (local)", name),
+        )
+        .to_string();
+
+    for line in unified.lines() {
+        let line = if line.starts_with("---") || line.starts_with("+++") {
+            line.bold()
+        } else if line.starts_with("@@") {
+            line.cyan()
+        } else if line.starts_with('+') {
+            line.green()
+        } else if line.starts_with('-') {
+            line.red()
+        } else {
+            line.normal()
+        };
+        human!("{}", line);
+    }
+
+    true
+}
+
+// diff_revision diffs the stacks against the same stacks in the config
+// loaded at a git revision, returns the names of the stacks that differ
+fn diff_revision(
+    old_conf: &config::Config,
+    selected_stacks: &[&stacks::Stack],
+    git_ref: &str,
+) -> Result<Vec<String>, String> {
+    let mut changed = Vec::new();
+    for stack in selected_stacks {
+        let old = old_conf.stacks.iter().find(|s| s.name == stack.name);
+        if print_resource_diffs(&stack.name, git_ref, old, Some(stack))? {
+            changed.push(stack.name.clone());
+        } else {
+            log::info!("[{}] {}", stack.name.cyan(), "no differences".green());
+        }
+    }
+
+    Ok(changed)
+}
+
+// print_resource_diffs prints the resources added, removed and changed
+// between two versions of a stack, returns true if there are any
+fn print_resource_diffs(
+    name: &str,
+    git_ref: &str,
+    old: Option<&stacks::Stack>,
+    new: Option<&stacks::Stack>,
+) -> Result<bool, String> {
+    let render = |s: Option<&stacks::Stack>| -> Result<Value, String> {
+        match s {
+            Some(s) => {
+                template::parse(&s.generate_template()?).map_err(|e| format!("[{}] {}", s.name, e))
+            }
+            None => Ok(Value::Null),
+        }
+    };
+
+    let diffs = template::diff_resources(&render(old)?, &render(new)?);
+    if diffs.is_empty() && old.is_some() && new.is_some() {
+        return Ok(false);
+    }
+
+    let count = |action: DiffAction| diffs.iter().filter(|d| d.action == action).count();
+    let status = match (old, new) {
+        (None, _) => format!("stack added since {}", git_ref),
+        (_, None) => format!("stack removed since {}", git_ref),
+        _ => format!("changed since {}", git_ref),
+    };
+    human!(
+        "{} {}: {} added, {} removed, {} changed",
+        format!("[{}]", name).bold(),
+        status,
+        count(DiffAction::Added),
+        count(DiffAction::Removed),
+        count(DiffAction::Changed)
+    );
+
+    for d in &diffs {
+        let resource = format!("{} ({})", d.logical_id, d.resource_type);
+        match d.action {
+            DiffAction::Added => human!("{}", format!("+ {}", resource).green()),
+            DiffAction::Removed => human!("{}", format!("- {}", resource).red()),
+            DiffAction::Changed => human!("{}", format!("~ {}", resource).yellow()),
+        }
+
+        for c in &d.changes {
+            let value = |v: &Option<Value>| {
+                v.as_ref()
+                    .map(|v| v.to_string())
+                    .unwrap_or("(none)".to_string())
+            };
+            human!(
+                "    {}: {} -> {}",
+                c.path,
+                value(&c.old).red(),
+                value(&c.new).green()
+            );
+        }
+    }
+    human!();
+
+    Ok(true)
+}
+
+// load_config_at loads the config as it was at a git revision. The
+// revision is checked out to a temporary worktree and the config is
+// evaluated from there, so the local files it reads are taken from
+// the same revision
+fn load_config_at(config_path: &str, git_ref: &str) -> Result<config::Config, String> {
+    if config_path.starts_with("http://") || config_path.starts_with("https://") {
+        return Err("--against requires a local config file".to_string());
+    }
+
+    let config_path = fs::canonicalize(config_path)
+        .map_err(|e| format!("failed to resolve config path [{}]: {}", config_path, e))?;
+    let config_dir = config_path.parent().unwrap_or(Path::new("/"));
+    let root = PathBuf::from(git(config_dir, &["rev-parse", "--show-toplevel"])?);
+    let root = fs::canonicalize(&root).unwrap_or(root);
+
+    let tmp = TempDir::new("kloi-diff").map_err(|e| e.to_string())?;
+    let worktree = tmp.path().join("tree");
+    let worktree_arg = worktree.to_string_lossy().to_string();
+    git(
+        &root,
+        &["worktree", "add", "--detach", &worktree_arg, git_ref],
+    )?;
+
+    // relative paths in the config are resolved from the working directory,
+    // use the same directory within the worktree while loading the config
+    let cwd = env::current_dir().map_err(|e| e.to_string())?;
+    let rel_cwd = fs::canonicalize(&cwd)
+        .ok()
+        .and_then(|c| c.strip_prefix(&root).ok().map(|p| p.to_path_buf()))
+        .unwrap_or_else(|| {
+            config_dir
+                .strip_prefix(&root)
+                .unwrap_or(Path::new(""))
+                .to_path_buf()
+        });
+    let rel_config = config_path.strip_prefix(&root).unwrap_or(&config_path);
+
+    log::debug!("loading config [{}] at [{}]", rel_config.display(), git_ref);
+    let loaded = env::set_current_dir(worktree.join(&rel_cwd))
+        .map_err(|e| {
+            format!(
+                "failed to change to [{}] at [{}]: {}",
+                rel_cwd.display(),
+                git_ref,
+                e
+            )
+        })
+        .and_then(|_| {
+            config::load_config_from_file(worktree.join(rel_config).to_string_lossy().to_string())
+        })
+        .map_err(|e| format!("failed to load config at [{}]: {}", git_ref, e));
+
+    // always restore the working directory and remove the worktree
+    let restored = env::set_current_dir(&cwd).map_err(|e| e.to_string());
+    if let Err(e) = git(&root, &["worktree", "remove", "--force", &worktree_arg]) {
+        log::warn!("failed to remove worktree [{}]: {}", worktree_arg, e);
+    }
+
+    restored?;
+    loaded
+}
+
+// git runs a git command in a directory and returns its trimmed output
+fn git(dir: &Path, args: &[&str]) -> Result<String, String> {
+    let output = process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| format!("failed to run git: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -0,0 +1,333 @@
+use aws_config::{self, BehaviorVersion};
+use aws_sdk_cloudformation::types::{
+    PropertyDifference, StackDriftDetectionStatus, StackDriftStatus, StackResourceDrift,
+    StackResourceDriftStatus,
+};
+use aws_sdk_cloudformation::Client;
+use aws_types::region::Region;
+use clap::ArgMatches;
+use clap::{arg, Command};
+use colored::{ColoredString, Colorize};
+use log;
+use std::env;
+use tokio::time::{sleep, Duration};
+
+use crate::config;
+use crate::output::{self, human};
+use crate::stacks;
+use crate::utils;
+
+const ABOUT: &str = r#"detect drift between stacks and their deployed resources,
+exits with code 2 if drift is found and 1 on errors
+"#;
+
+pub fn command() -> Command {
+    Command::new("drift")
+        .about(ABOUT.truecolor(125, 174, 189).to_string())
+        .arg(arg!([stack]))
+        .arg(arg!(-A --all ... "detect drift of all stacks"))
+        .arg(arg!(-c --config <FILE> "path to config file"))
+}
+
+pub async fn handle(matches: &ArgMatches) -> Result<(), String> {
+    let mut config_path = env::var("KLOI_CONFIG").ok();
+
+    // if config is not set by env, check if it is set by cli
+    if config_path.is_none() {
+        log::debug!("config path is not set by env, KLOI_CONFIG, check CLI -c/--config");
+        config_path = Some(matches
+            .get_one::<String>("config")
+            .ok_or_else(|| "config file required, please supply using -c/--config or set the KLOI_CONFIG env var".to_string())?.to_string());
+    };
+
+    // load config and create client
+    // note: unwrap is fine here, since we've already checked if config is set above
+    let conf = config::load_config_from_file(config_path.unwrap())?;
+
+    let drift_all = matches.get_one::<u8>("all").unwrap_or(&0);
+    let selected_stacks: Vec<&stacks::Stack> = if *drift_all == 1 {
+        conf.stacks.iter().collect()
+    } else {
+        match matches.get_one::<String>("stack") {
+            Some(c) => {
+                let stack = conf
+                    .stacks
+                    .iter()
+                    .find(|s| &s.name == c)
+                    .ok_or_else(|| format!("stack [{}] not found", c))?;
+                vec![stack]
+            }
+            None => {
+                // if no stack is specified, use interactive form
+                let opts = conf
+                    .stacks
+                    .iter()
+                    .map(|s| s.name.clone())
+                    .collect::<Vec<String>>();
+                let selected = utils::multiselect(opts, "select stack");
+                conf.stacks
+                    .iter()
+                    .filter(|s| selected.contains(&s.name))
+                    .collect()
+            }
+        }
+    };
+
+    if selected_stacks.is_empty() {
+        return Err("no stacks found".to_string());
+    }
+
+    let mut drifted = Vec::new();
+    for stack in selected_stacks {
+        // create client per stack
+        let region = stack.region.clone().unwrap_or("eu-west-1".to_string());
+        let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+            .region(Region::new(region))
+            .load()
+            .await;
+        let client = aws_sdk_cloudformation::Client::new(&sdk_config);
+
+        if utils::stack_exists(&client, &stack.name).await.is_err() {
+            log::warn!("[{}] {}", stack.name.cyan(), "does not exist".yellow());
+            continue;
+        }
+
+        log::info!("[{}] detecting drift", stack.name.cyan());
+        let status = detect_drift(&client, &stack.name).await?;
+        human!("[{}] {}", stack.name.cyan(), colored_status(&status));
+
+        let drifts = resource_drifts(&client, &stack.name).await?;
+        for drift in drifts.iter() {
+            print_resource_drift(drift);
+        }
+
+        if is_drifted(&status, &drifts) {
+            drifted.push(stack.name.clone());
+        }
+    }
+
+    if !drifted.is_empty() {
+        output::differences(
+            "drift",
+            &format!("drift found in stacks: {}", drifted.join(", ")),
+        );
+    }
+
+    Ok(())
+}
+
+// detect_drift starts drift detection for a stack and waits for it to finish
+pub async fn detect_drift(client: &Client, stack_name: &str) -> Result<StackDriftStatus, String> {
+    let res = client
+        .detect_stack_drift()
+        .stack_name(stack_name)
+        .send()
+        .await
+        .map_err(|e| {
+            format!(
+                "[{}] error starting drift detection: {}",
+                stack_name,
+                e.into_service_error()
+            )
+        })?;
+
+    let detection_id = res
+        .stack_drift_detection_id()
+        .unwrap_or_default()
+        .to_string();
+    loop {
+        let res = client
+            .describe_stack_drift_detection_status()
+            .stack_drift_detection_id(&detection_id)
+            .send()
+            .await
+            .map_err(|e| {
+                format!(
+                    "[{}] error getting drift detection status: {}",
+                    stack_name,
+                    e.into_service_error()
+                )
+            })?;
+
+        match res.detection_status() {
+            Some(StackDriftDetectionStatus::DetectionInProgress) => {
+                sleep(Duration::from_secs(2)).await;
+                continue;
+            }
+            // detection fails when some resources couldn't be checked,
+            // the drift status of the others is still reported
+            Some(StackDriftDetectionStatus::DetectionFailed) => {
+                let reason = res.detection_status_reason().unwrap_or("unknown reason");
+                match res.stack_drift_status() {
+                    Some(status) => {
+                        log::warn!("[{}] drift detection incomplete: {}", stack_name, reason);
+                        return Ok(status.clone());
+                    }
+                    None => {
+                        return Err(format!(
+                            "[{}] drift detection failed: {}",
+                            stack_name, reason
+                        ))
+                    }
+                }
+            }
+            _ => {
+                return Ok(res
+                    .stack_drift_status()
+                    .cloned()
+                    .unwrap_or(StackDriftStatus::NotChecked))
+            }
+        }
+    }
+}
+
+// resource_drifts returns the drift of every resource in a stack,
+// as recorded by the last drift detection
+pub async fn resource_drifts(
+    client: &Client,
+    stack_name: &str,
+) -> Result<Vec<StackResourceDrift>, String> {
+    let pages = client
+        .describe_stack_resource_drifts()
+        .stack_name(stack_name)
+        .into_paginator()
+        .send()
+        .collect::<Result<Vec<_>, _>>()
+        .await
+        .map_err(|e| {
+            format!(
+                "[{}] error describing resource drifts: {}",
+                stack_name,
+                e.into_service_error()
+            )
+        })?;
+
+    let mut drifts: Vec<StackResourceDrift> = pages
+        .iter()
+        .flat_map(|p| p.stack_resource_drifts().to_vec())
+        .collect();
+
+    drifts.sort_by(|a, b| a.logical_resource_id().cmp(&b.logical_resource_id()));
+    Ok(drifts)
+}
+
+// is_drifted tells if a stack drifted. Resources found modified or deleted
+// count too, incomplete detections don't report the stack as drifted
+fn is_drifted(status: &StackDriftStatus, drifts: &[StackResourceDrift]) -> bool {
+    *status == StackDriftStatus::Drifted
+        || drifts.iter().any(|d| {
+            matches!(
+                d.stack_resource_drift_status(),
+                Some(StackResourceDriftStatus::Modified | StackResourceDriftStatus::Deleted)
+            )
+        })
+}
+
+// colored_status colors a stack drift status for display
+pub fn colored_status(status: &StackDriftStatus) -> ColoredString {
+    let text = status.as_str().to_lowercase();
+    match status {
+        StackDriftStatus::InSync => text.green(),
+        StackDriftStatus::Drifted => text.red(),
+        _ => text.truecolor(96, 96, 96),
+    }
+}
+
+// print_resource_drift prints the drift status of a resource along
+// with the expected and actual value of every drifted property
+fn print_resource_drift(drift: &StackResourceDrift) {
+    let status = drift
+        .stack_resource_drift_status()
+        .map(|s| s.as_str().to_lowercase())
+        .unwrap_or("-".to_string());
+    let status = match drift.stack_resource_drift_status() {
+        Some(StackResourceDriftStatus::InSync) => status.green(),
+        Some(StackResourceDriftStatus::Modified) => status.yellow(),
+        Some(StackResourceDriftStatus::Deleted) => status.red(),
+        _ => status.truecolor(96, 96, 96),
+    };
+
+    human!(
+        "  {} ({}) {}",
+        drift.logical_resource_id().unwrap_or("-"),
+        drift.resource_type().unwrap_or("-"),
+        status
+    );
+
+    for diff in drift.property_differences() {
+        human!("    {}", format_difference(diff));
+    }
+}
+
+// format_difference renders the expected and actual value of a drifted property
+fn format_difference(diff: &PropertyDifference) -> String {
+    format!(
+        "{} [{}]: expected {} actual {}",
+        diff.property_path().unwrap_or("-"),
+        diff.difference_type()
+            .map(|t| t.as_str().to_lowercase())
+            .unwrap_or_default(),
+        diff.expected_value().unwrap_or("(none)").green(),
+        diff.actual_value().unwrap_or("(none)").red()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_cloudformation::types::DifferenceType;
+
+    fn drift(status: StackResourceDriftStatus) -> StackResourceDrift {
+        StackResourceDrift::builder()
+            .logical_resource_id("Queue")
+            .resource_type("AWS::SQS::Queue")
+            .stack_resource_drift_status(status)
+            .set_timestamp(Some(
+                aws_sdk_cloudformation::primitives::DateTime::from_secs(0),
+            ))
+            .build()
+    }
+
+    #[test]
+    fn test_is_drifted() {
+        let in_sync = [drift(StackResourceDriftStatus::InSync)];
+        assert!(is_drifted(&StackDriftStatus::Drifted, &[]));
+        assert!(!is_drifted(&StackDriftStatus::InSync, &in_sync));
+        assert!(!is_drifted(&StackDriftStatus::NotChecked, &[]));
+
+        // detection that couldn't check every resource still
+        // reports the resources it found drifted
+        for status in [
+            StackResourceDriftStatus::Modified,
+            StackResourceDriftStatus::Deleted,
+        ] {
+            assert!(is_drifted(&StackDriftStatus::NotChecked, &[drift(status)]));
+        }
+        assert!(!is_drifted(
+            &StackDriftStatus::InSync,
+            &[drift(StackResourceDriftStatus::NotChecked)]
+        ));
+    }
+
+    #[test]
+    fn test_format_difference() {
+        let diff = PropertyDifference::builder()
+            .property_path("/Properties/DelaySeconds")
+            .expected_value("5")
+            .actual_value("10")
+            .difference_type(DifferenceType::NotEqual)
+            .build();
+        let line = format_difference(&diff);
+        assert!(line.starts_with("/Properties/DelaySeconds [not_equal]: expected "));
+        assert!(line.contains('5') && line.contains("10"));
+
+        // removed properties have no actual value
+        let diff = PropertyDifference::builder()
+            .property_path("/Properties/Tags/0")
+            .expected_value("{\"Key\":\"team\"}")
+            .actual_value("")
+            .difference_type(DifferenceType::Remove)
+            .build();
+        assert!(format_difference(&diff).starts_with("/Properties/Tags/0 [remove]: expected "));
+    }
+}
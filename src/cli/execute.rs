@@ -0,0 +1,180 @@
+use aws_config::{self, BehaviorVersion};
+use aws_sdk_cloudformation::error::ProvideErrorMetadata;
+use aws_sdk_cloudformation::types::{ChangeSetStatus, ExecutionStatus};
+use aws_types::region::Region;
+use clap::ArgMatches;
+use clap::{arg, Command};
+use colored::Colorize;
+use log;
+use std::env;
+use std::fs;
+
+use crate::apply;
+use crate::config;
+use crate::plan::{self, ChangeSet, SavedPlan};
+use crate::utils;
+use utils::exec_jobs;
+
+const ABOUT: &str = r#"execute a plan saved with `kloi plan --save`,
+plans that no longer match the config are refused
+"#;
+
+pub fn command() -> Command {
+    Command::new("execute")
+        .about(ABOUT.truecolor(125, 174, 189).to_string())
+        .arg(arg!(<plan> "name of the saved plan or path to a plan file"))
+        .arg(arg!(--"allow-replacement" "allow plans that replace protected stateful resources"))
+        .arg(arg!(-c --config <FILE> "path to config file"))
+}
+
+pub async fn handle(matches: &ArgMatches) -> Result<(), String> {
+    let mut config_path = env::var("KLOI_CONFIG").ok();
+
+    // if config is not set by env, check if it is set by cli
+    if config_path.is_none() {
+        log::debug!("config path is not set by env, KLOI_CONFIG, check CLI -c/--config");
+        config_path = Some(matches
+            .get_one::<String>("config")
+            .ok_or_else(|| "config file required, please supply using -c/--config or set the KLOI_CONFIG env var".to_string())?.to_string());
+    };
+
+    // load config and create client
+    // note: unwrap is fine here, since we've already checked if config is set above
+    let conf = config::load_config_from_file(config_path.unwrap())?;
+
+    // plan is a required argument
+    let (saved, path) = SavedPlan::load(matches.get_one::<String>("plan").unwrap())?;
+
+    let stack = conf
+        .stacks
+        .iter()
+        .find(|s| s.name == saved.stack)
+        .ok_or_else(|| format!("stack [{}] of plan [{}] not found", saved.stack, saved.name))?
+        .clone();
+
+    // compare the config with the inputs the change set was created from.
+    // The change set holds the parameter values, so nothing is prompted
+    if let Some(reason) = saved.stale(&stack)? {
+        return Err(stale(&saved, reason));
+    }
+
+    let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+        .region(Region::new(saved.region.clone()))
+        .load()
+        .await;
+    let client = aws_sdk_cloudformation::Client::new(&sdk_config);
+
+    // the change set becomes obsolete if the stack was updated since
+    let res = client
+        .describe_change_set()
+        .change_set_name(&saved.change_set_id)
+        .send()
+        .await
+        .map_err(|e| {
+            stale(
+                &saved,
+                e.message().unwrap_or("the change set could not be found"),
+            )
+        })?;
+
+    if res.status() != Some(&ChangeSetStatus::CreateComplete)
+        || res.execution_status() != Some(&ExecutionStatus::Available)
+    {
+        return Err(stale(
+            &saved,
+            &format!(
+                "the change set is {}",
+                res.execution_status()
+                    .map(|s| s.as_str())
+                    .unwrap_or("unavailable")
+                    .to_lowercase()
+            ),
+        ));
+    }
+
+    let cs = ChangeSet {
+        id: saved.change_set_id.clone(),
+        stack_name: saved.stack.clone(),
+        create: saved.create,
+        changes: plan::change_set_changes(&client, &saved.change_set_id).await?,
+    };
+
+    plan::print_changes(&cs);
+
+    if !matches.get_flag("allow-replacement") {
+        plan::check_replacements(&stack, &cs)?;
+    }
+
+    if cs.create {
+        exec_jobs!(on_create, &stack, stack.name.clone(), false);
+        plan::execute_change_set(&client, &stack, &cs).await?;
+        apply::update_termination_protection(&client, &stack).await?;
+        exec_jobs!(on_create, &stack, stack.name.clone(), true);
+    } else {
+        exec_jobs!(on_update, &stack, stack.name.clone(), false);
+        // post-update hooks only run when the stack was updated
+        if plan::execute_change_set(&client, &stack, &cs).await? {
+            apply::update_termination_protection(&client, &stack).await?;
+            exec_jobs!(on_update, &stack, stack.name.clone(), true);
+        }
+    }
+
+    // executed plans can't be executed again
+    if let Err(e) = fs::remove_file(&path) {
+        log::warn!("failed to remove plan {}: {}", path.display(), e);
+    }
+
+    Ok(())
+}
+
+fn stale(plan: &SavedPlan, reason: &str) -> String {
+    format!(
+        "[{}] plan [{}] is stale: {}, create a new plan with `kloi plan --save`",
+        plan.stack, plan.name, reason
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::Fingerprint;
+
+    fn load_stack(config: &str) -> crate::stacks::Stack {
+        config::load_test_config(config).stacks.remove(0)
+    }
+
+    #[test]
+    fn test_stale_plan() {
+        let config = r#"stacks.add(stacks.new(name = "app", region = "eu-west-1", template = "none", inputs = stacks.inputs(parameters = {"Env": "prod"}), options = stacks.options(tags = {"team": "platform"})))"#;
+        let stack = load_stack(config);
+        let saved = SavedPlan {
+            name: "release".to_string(),
+            stack: "app".to_string(),
+            region: "eu-west-1".to_string(),
+            change_set_id: "id".to_string(),
+            create: false,
+            fingerprint: Fingerprint::new(&stack).unwrap(),
+            created_at: String::new(),
+        };
+        assert_eq!(saved.stale(&stack).unwrap(), None);
+
+        let cases = [
+            (
+                config.replace("eu-west-1", "us-east-1"),
+                "the stack region changed",
+            ),
+            (
+                config.replace("\"none\"", "\"other\""),
+                "the template changed",
+            ),
+            (
+                config.replace("\"prod\"", "\"dev\""),
+                "the parameters changed",
+            ),
+            (config.replace("platform", "data"), "the tags changed"),
+        ];
+        for (config, reason) in cases {
+            assert_eq!(saved.stale(&load_stack(&config)).unwrap(), Some(reason));
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use aws_config::{self, BehaviorVersion};
+use aws_sdk_cloudformation::types::Stack;
+use aws_types::region::Region;
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use clap::{arg, Command};
+use colored::Colorize;
+use log;
+use std::env;
+
+use crate::config;
+use crate::graph::Graph;
+use crate::stacks;
+use crate::utils;
+use crate::utils::stack_request_result_handle;
+
+const ABOUT: &str = r#"deletes ephemeral stacks whose ttl has expired,
+stacks are discovered in every region referenced by the config
+"#;
+
+// ExpiredStack is a deployed stack whose ttl has elapsed
+struct ExpiredStack {
+    name: String,
+    region: String,
+    ttl: String,
+    expired_for: chrono::Duration,
+}
+
+pub fn command() -> Command {
+    Command::new("gc")
+        .about(ABOUT.truecolor(125, 174, 189).to_string())
+        .arg(arg!(--"dry-run" "list expired stacks without deleting them"))
+        .arg(arg!(-c --config <FILE> "path to config file"))
+}
+
+pub async fn handle(matches: &ArgMatches) -> Result<(), String> {
+    let mut config_path = env::var("KLOI_CONFIG").ok();
+
+    // if config is not set by env, check if it is set by cli
+    if config_path.is_none() {
+        log::debug!("config path is not set by env, KLOI_CONFIG, check CLI -c/--config");
+        config_path = Some(matches
+            .get_one::<String>("config")
+            .ok_or_else(|| "config file required, please supply using -c/--config or set the KLOI_CONFIG env var".to_string())?.to_string());
+    };
+
+    // load config and create client
+    // note: unwrap is fine here, since we've already checked if config is set above
+    let conf = config::load_config_from_file(config_path.unwrap())?;
+    let dry_run = matches.get_flag("dry-run");
+
+    let now = Utc::now();
+    let mut expired: Vec<ExpiredStack> = Vec::new();
+    for region in conf.regions().iter() {
+        log::debug!("looking up expired stacks in region: {}", region);
+        let deployed = utils::list_stacks(region).await?;
+        expired.extend(expired_stacks(&conf, region, &deployed, now));
+    }
+
+    if expired.is_empty() {
+        log::info!("no expired stacks found");
+        return Ok(());
+    }
+
+    // delete dependents before their dependencies. Stacks that are not
+    // part of the config have no known dependencies and are deleted last
+    let expired = delete_order(&conf, expired)?;
+
+    for stack in expired.iter() {
+        log::info!(
+            "[{}] {} ttl [{}] expired {} ago",
+            stack.name.cyan(),
+            stack.region.truecolor(96, 96, 96),
+            stack.ttl,
+            format_duration(stack.expired_for).yellow()
+        );
+    }
+
+    if dry_run {
+        return Ok(());
+    }
+
+    for stack in expired.iter() {
+        let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+            .region(Region::new(stack.region.clone()))
+            .load()
+            .await;
+        let client = aws_sdk_cloudformation::Client::new(&sdk_config);
+
+        let token = utils::request_token();
+        let res = client
+            .delete_stack()
+            .stack_name(stack.name.clone())
+            .client_request_token(&token)
+            .send()
+            .await;
+
+        stack_request_result_handle!(res, stack.name, "delete stack");
+
+        utils::stackprogress(
+            &client,
+            &stack.name,
+            None,
+            stack.region.clone(),
+            utils::WaitEvent::Delete,
+            Some(&token),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+// expired_stacks returns the deployed stacks of a region owned by the
+// config that carry a ttl tag which has elapsed since they were created
+fn expired_stacks(
+    conf: &config::Config,
+    region: &str,
+    deployed: &[Stack],
+    now: DateTime<Utc>,
+) -> Vec<ExpiredStack> {
+    let mut expired = Vec::new();
+    for s in deployed.iter() {
+        let Some(ttl) = utils::stack_tag(s, stacks::TTL_TAG) else {
+            continue;
+        };
+        if !owned(conf, region, s) {
+            continue;
+        }
+
+        let status = s.stack_status().map(|s| s.as_str()).unwrap_or_default();
+        if status.starts_with("DELETE_") {
+            continue;
+        }
+
+        let name = s.stack_name().unwrap_or_default().to_string();
+        let duration = match stacks::parse_ttl(ttl) {
+            Ok(d) => d,
+            Err(e) => {
+                log::warn!("[{}] skipping stack: {}", name.cyan(), e);
+                continue;
+            }
+        };
+
+        let Some(created) = s
+            .creation_time()
+            .and_then(|t| DateTime::from_timestamp(t.secs(), t.subsec_nanos()))
+        else {
+            continue;
+        };
+
+        let expires = created + duration;
+        if expires > now {
+            log::debug!("[{}] expires at {}", name, expires.to_rfc3339());
+            continue;
+        }
+
+        expired.push(ExpiredStack {
+            name,
+            region: region.to_string(),
+            ttl: ttl.to_string(),
+            expired_for: now - expires,
+        });
+    }
+
+    expired
+}
+
+// owned tells if a deployed stack belongs to the config. Other configs
+// deploy to the same accounts and regions, so stacks are matched by the
+// project tag, or by the config stacks when no project is set
+fn owned(conf: &config::Config, region: &str, s: &Stack) -> bool {
+    if let Some(project) = &conf.project {
+        return utils::stack_tag(s, stacks::PROJECT_TAG) == Some(project.as_str());
+    }
+
+    let name = s.stack_name().unwrap_or_default();
+    conf.stacks
+        .iter()
+        .any(|c| c.name == name && c.region.clone().unwrap_or("eu-west-1".to_string()) == region)
+}
+
+// format_duration renders a duration in its largest whole unit
+fn format_duration(d: chrono::Duration) -> String {
+    if d.num_days() > 0 {
+        format!("{}d", d.num_days())
+    } else if d.num_hours() > 0 {
+        format!("{}h", d.num_hours())
+    } else if d.num_minutes() > 0 {
+        format!("{}m", d.num_minutes())
+    } else {
+        format!("{}s", d.num_seconds())
+    }
+}
+
+// delete_order sorts expired stacks so that dependents come before their
+// dependencies, following the dependency graph of the config stacks
+fn delete_order(
+    conf: &config::Config,
+    mut expired: Vec<ExpiredStack>,
+) -> Result<Vec<ExpiredStack>, String> {
+    let in_region = |s: &stacks::Stack, e: &ExpiredStack| {
+        s.name == e.name && s.region.clone().unwrap_or("eu-west-1".to_string()) == e.region
+    };
+
+    let known: Vec<stacks::Stack> = expired
+        .iter()
+        .filter_map(|e| conf.stacks.iter().find(|s| in_region(s, e)).cloned())
+        .collect();
+    let graph = Graph::new(known, true)?;
+
+    let mut ordered = Vec::new();
+    for i in graph.order() {
+        if let Some(pos) = expired.iter().position(|e| in_region(&graph.stacks[i], e)) {
+            ordered.push(expired.remove(pos));
+        }
+    }
+    ordered.append(&mut expired);
+
+    Ok(ordered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_cloudformation::primitives::DateTime as SmithyTime;
+    use aws_sdk_cloudformation::types::Tag;
+
+    fn deployed(name: &str, project: Option<&str>) -> Stack {
+        let tag = |k: &str, v: &str| Tag::builder().key(k).value(v).build();
+        let mut tags = vec![tag(stacks::TTL_TAG, "1h")];
+        if let Some(p) = project {
+            tags.push(tag(stacks::PROJECT_TAG, p));
+        }
+        Stack::builder()
+            .stack_name(name)
+            .creation_time(SmithyTime::from_secs(0))
+            .set_tags(Some(tags))
+            .build()
+    }
+
+    #[test]
+    fn test_expired_stacks() {
+        let stacks = vec![
+            deployed("app", Some("web")),
+            deployed("preview", Some("web")),
+            deployed("billing", Some("payments")),
+            deployed("legacy", None),
+        ];
+        let names = |conf: &config::Config| -> Vec<String> {
+            expired_stacks(conf, "eu-west-1", &stacks, Utc::now())
+                .into_iter()
+                .map(|e| e.name)
+                .collect()
+        };
+
+        // stacks of another project are never expired by this config
+        let conf = config::load_test_config(
+            r#"
+stacks.project("web")
+stacks.add(stacks.new(name = "app", region = "eu-west-1", template = "none"))
+"#,
+        );
+        assert_eq!(names(&conf), vec!["app", "preview"]);
+
+        // without a project only the stacks of the config are considered
+        let conf = config::load_test_config(
+            r#"
+stacks.add(stacks.new(name = "legacy", region = "eu-west-1", template = "none"))
+stacks.add(stacks.new(name = "billing", region = "us-east-1", template = "none"))
+"#,
+        );
+        assert_eq!(names(&conf), vec!["legacy"]);
+    }
+
+    #[test]
+    fn test_delete_order() {
+        let conf = config::load_test_config(
+            r#"
+stacks.add(stacks.new(name = "network", region = "eu-west-1", template = "none"))
+stacks.add(stacks.new(name = "db", region = "eu-west-1", template = "none", depends_on = ["network"]))
+"#,
+        );
+
+        let expired = ["network", "other", "db"]
+            .iter()
+            .map(|name| ExpiredStack {
+                name: name.to_string(),
+                region: "eu-west-1".to_string(),
+                ttl: "1h".to_string(),
+                expired_for: chrono::Duration::hours(1),
+            })
+            .collect();
+
+        let order: Vec<String> = delete_order(&conf, expired)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(order, vec!["db", "network", "other"]);
+    }
+}
@@ -0,0 +1,334 @@
+use aws_config::{self, BehaviorVersion};
+use aws_sdk_cloudformation::types::{Stack, TemplateStage};
+use aws_sdk_cloudformation::Client;
+use aws_types::region::Region;
+use clap::ArgMatches;
+use clap::{arg, Command};
+use colored::Colorize;
+use log;
+use std::fs;
+use std::path::Path;
+
+use crate::output::human;
+use crate::stacks;
+use crate::template;
+
+const ABOUT: &str = r#"import existing stacks into kloi,
+writes the deployed templates to files and prints the matching config
+"#;
+
+pub fn command() -> Command {
+    Command::new("import")
+        .about(ABOUT.truecolor(125, 174, 189).to_string())
+        .arg(arg!(<stack> ... "names of the stacks to import"))
+        .arg(arg!(-r --region <REGION> "region of the stacks").default_value("eu-west-1"))
+        .arg(
+            arg!(-d --dir <DIR> "directory the templates are written to")
+                .default_value("templates"),
+        )
+        .arg(arg!(-f --force "overwrite existing template files"))
+}
+
+pub async fn handle(matches: &ArgMatches) -> Result<(), String> {
+    // note: unwraps are fine here, the arguments are required or have defaults
+    let region = matches.get_one::<String>("region").unwrap();
+    let dir = matches.get_one::<String>("dir").unwrap();
+    let names: Vec<&String> = matches.get_many::<String>("stack").unwrap().collect();
+
+    let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+        .region(Region::new(region.clone()))
+        .load()
+        .await;
+    let client = aws_sdk_cloudformation::Client::new(&sdk_config);
+
+    fs::create_dir_all(dir).map_err(|e| format!("failed to create [{}]: {}", dir, e))?;
+
+    let mut snippets = Vec::new();
+    for name in names {
+        let stack = describe_stack(&client, name).await?;
+        let body = client
+            .get_template()
+            .stack_name(name)
+            .template_stage(TemplateStage::Original)
+            .send()
+            .await
+            .map_err(|e| {
+                format!(
+                    "[{}] error getting template: {}",
+                    name,
+                    e.into_service_error()
+                )
+            })?
+            .template_body()
+            .unwrap_or_default()
+            .to_string();
+
+        // keep the format of the deployed template
+        let ext = if body.trim_start().starts_with('{') {
+            "json"
+        } else {
+            "yaml"
+        };
+        let path = Path::new(dir).join(format!("{}.{}", name, ext));
+        if path.exists() && !matches.get_flag("force") {
+            return Err(format!(
+                "[{}] {} already exists, use --force to overwrite it",
+                name,
+                path.display()
+            ));
+        }
+
+        fs::write(&path, &body)
+            .map_err(|e| format!("[{}] failed to write {}: {}", name, path.display(), e))?;
+        log::info!(
+            "[{}] {} {}",
+            name.cyan(),
+            "template written to".green(),
+            path.display()
+        );
+
+        // NoEcho values can't be read back, they are left out of the config
+        // so apply prompts for them instead of deploying an empty value
+        let no_echo: Vec<String> = template::parse(&body)
+            .map(|t| {
+                template::parameters(&t)
+                    .into_iter()
+                    .filter(|(_, p)| p.no_echo)
+                    .map(|(k, _)| k)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        snippets.push(snippet(
+            &stack,
+            region,
+            &path.display().to_string(),
+            &no_echo,
+        ));
+    }
+
+    human!("{}", snippets.join("\n"));
+    Ok(())
+}
+
+async fn describe_stack(client: &Client, name: &str) -> Result<Stack, String> {
+    let res = client
+        .describe_stacks()
+        .stack_name(name)
+        .send()
+        .await
+        .map_err(|e| {
+            format!(
+                "[{}] error describing stack: {}",
+                name,
+                e.into_service_error()
+            )
+        })?;
+
+    res.stacks()
+        .first()
+        .cloned()
+        .ok_or_else(|| format!("stack [{}] not found", name))
+}
+
+// snippet renders the stacks.new call matching a deployed stack
+pub fn snippet(s: &Stack, region: &str, template_path: &str, no_echo: &[String]) -> String {
+    let name = s.stack_name().unwrap_or_default();
+    let var = identifier(name);
+
+    let mut args = vec![
+        format!("name = {}", quote(name)),
+        format!("region = {}", quote(region)),
+        format!("template = os.open({})", quote(template_path)),
+    ];
+
+    // arguments of the stacks.inputs, stacks.options and stacks.lifecycle groups
+    let mut inputs = Vec::new();
+    let mut options = Vec::new();
+    let mut lifecycle = Vec::new();
+
+    let mut params: Vec<(&str, &str)> = s
+        .parameters()
+        .iter()
+        .filter_map(|p| Some((p.parameter_key()?, p.parameter_value().unwrap_or_default())))
+        .collect();
+    params.sort();
+    if !params.is_empty() {
+        let entries: Vec<String> = params
+            .iter()
+            .map(|(k, v)| {
+                if no_echo.iter().any(|n| n == k) {
+                    format!(
+                        "            # {}: \"\",  # NoEcho, apply prompts for the value",
+                        quote(k)
+                    )
+                } else {
+                    format!("            {}: {},", quote(k), quote(v))
+                }
+            })
+            .collect();
+        inputs.push(format!(
+            "parameters = {{\n{}\n        }}",
+            entries.join("\n")
+        ));
+    }
+
+    if !s.capabilities().is_empty() {
+        let caps: Vec<String> = s.capabilities().iter().map(|c| quote(c.as_str())).collect();
+        options.push(format!("capabilities = [{}]", caps.join(", ")));
+    }
+
+    // tags kloi manages are set from the stack config instead
+    let mut tags: Vec<(&str, &str)> = s
+        .tags()
+        .iter()
+        .filter_map(|t| Some((t.key()?, t.value().unwrap_or_default())))
+        .filter(|(k, _)| !k.starts_with("kloi:"))
+        .collect();
+    tags.sort();
+    if !tags.is_empty() {
+        let entries: Vec<String> = tags
+            .iter()
+            .map(|(k, v)| format!("            {}: {},", quote(k), quote(v)))
+            .collect();
+        options.push(format!("tags = {{\n{}\n        }}", entries.join("\n")));
+    }
+
+    if let Some(ttl) = s
+        .tags()
+        .iter()
+        .find(|t| t.key() == Some(stacks::TTL_TAG))
+        .and_then(|t| t.value())
+    {
+        lifecycle.push(format!("ttl = {}", quote(ttl)));
+    }
+
+    if let Some(role) = s.role_arn() {
+        options.push(format!("role_arn = {}", quote(role)));
+    }
+
+    if !s.notification_arns().is_empty() {
+        let arns: Vec<String> = s.notification_arns().iter().map(|a| quote(a)).collect();
+        options.push(format!("notification_arns = [{}]", arns.join(", ")));
+    }
+
+    if s.enable_termination_protection().unwrap_or(false) {
+        lifecycle.push("termination_protection = True".to_string());
+    }
+
+    for (group, group_args) in [
+        ("inputs", inputs),
+        ("options", options),
+        ("lifecycle", lifecycle),
+    ] {
+        if !group_args.is_empty() {
+            args.push(format!(
+                "{} = stacks.{}(\n        {},\n    )",
+                group,
+                group,
+                group_args.join(",\n        ")
+            ));
+        }
+    }
+
+    format!(
+        "{} = stacks.new(\n    {},\n)\nstacks.add({})\n",
+        var,
+        args.join(",\n    "),
+        var
+    )
+}
+
+// quote renders a starlark string literal
+fn quote(s: &str) -> String {
+    serde_json::to_string(s).unwrap_or_else(|_| format!("\"{}\"", s))
+}
+
+// identifier turns a stack name into a valid starlark variable name
+fn identifier(name: &str) -> String {
+    let mut id: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    if id.is_empty() || id.starts_with(|c: char| c.is_ascii_digit()) {
+        id.insert(0, '_');
+    }
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use aws_sdk_cloudformation::types::{Capability, Parameter, Tag};
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_import_snippet() {
+        let tmp_dir = TempDir::new("import").unwrap();
+        let template_path = tmp_dir.path().join("imported.yaml");
+        std::fs::write(&template_path, "Resources: {}").unwrap();
+
+        let deployed = Stack::builder()
+            .stack_name("imported-stack")
+            .parameters(
+                Parameter::builder()
+                    .parameter_key("Env")
+                    .parameter_value("prod")
+                    .build(),
+            )
+            .parameters(
+                Parameter::builder()
+                    .parameter_key("Secret")
+                    .parameter_value("****")
+                    .build(),
+            )
+            .capabilities(Capability::CapabilityIam)
+            .tags(Tag::builder().key("team").value("platform").build())
+            .tags(
+                Tag::builder()
+                    .key(stacks::MANAGED_TAG)
+                    .value("true")
+                    .build(),
+            )
+            .role_arn("arn:aws:iam::123456789012:role/deploy")
+            .notification_arns("arn:aws:sns:eu-west-1:123456789012:events")
+            .enable_termination_protection(true)
+            .build();
+
+        let snippet = snippet(
+            &deployed,
+            "eu-west-1",
+            &template_path.display().to_string(),
+            &["Secret".to_string()],
+        );
+        let config_path = tmp_dir.path().join("config.star");
+        std::fs::write(&config_path, &snippet).unwrap();
+        let config = config::load_config_from_file(config_path.display().to_string()).unwrap();
+        let stack = &config.stacks[0];
+
+        assert_eq!(stack.name, "imported-stack");
+        assert_eq!(
+            stack.parameters.as_ref().unwrap()["Env"],
+            serde_json::json!("prod")
+        );
+        // NoEcho parameters are left for apply to prompt for
+        assert!(snippet.contains("# \"Secret\""));
+        assert!(!stack.parameters.as_ref().unwrap().contains_key("Secret"));
+        assert_eq!(stack.capabilities, Some(vec!["CAPABILITY_IAM".to_string()]));
+        assert_eq!(stack.tags.as_ref().unwrap().len(), 1);
+        assert_eq!(
+            stack.role_arn.as_deref(),
+            Some("arn:aws:iam::123456789012:role/deploy")
+        );
+        assert_eq!(stack.notification_arns.as_ref().unwrap().len(), 1);
+        assert_eq!(stack.termination_protection, Some(true));
+    }
+}
@@ -1,8 +1,17 @@
 // CLI command mods
+pub mod adopt;
 pub mod apply;
 pub mod check;
 pub mod completions;
 pub mod delete;
+pub mod diff;
+pub mod drift;
+pub mod execute;
+pub mod gc;
+pub mod import;
+pub mod orphans;
+pub mod plan;
 pub mod show;
 pub mod status;
 pub mod utils;
+pub mod wait;
@@ -0,0 +1,298 @@
+use aws_config::{self, BehaviorVersion};
+use aws_sdk_cloudformation::error::ProvideErrorMetadata;
+use aws_sdk_cloudformation::Client;
+use aws_types::region::Region;
+use clap::ArgMatches;
+use clap::{arg, Command};
+use colored::Colorize;
+use log;
+use std::env;
+
+use crate::config;
+use crate::output::human;
+use crate::stacks;
+use crate::utils;
+use crate::utils::stack_request_result_handle;
+
+const ABOUT: &str = r#"lists stacks deployed from the config project that are no longer defined in it,
+stacks are discovered in every region referenced by the config
+"#;
+
+// Orphan is a deployed stack that has no matching stack in the config
+struct Orphan {
+    id: String,
+    name: String,
+    region: String,
+    status: String,
+}
+
+pub fn command() -> Command {
+    Command::new("orphans")
+        .about(ABOUT.truecolor(125, 174, 189).to_string())
+        .arg(
+            arg!(-p --prefix <PREFIX> "also treat untagged stacks with this name prefix as part of the project"),
+        )
+        .arg(arg!(--delete "delete the orphaned stacks"))
+        .arg(arg!(-y --yes "skip the delete confirmation prompt"))
+        .arg(arg!(-c --config <FILE> "path to config file"))
+}
+
+pub async fn handle(matches: &ArgMatches) -> Result<(), String> {
+    let mut config_path = env::var("KLOI_CONFIG").ok();
+
+    // if config is not set by env, check if it is set by cli
+    if config_path.is_none() {
+        log::debug!("config path is not set by env, KLOI_CONFIG, check CLI -c/--config");
+        config_path = Some(matches
+            .get_one::<String>("config")
+            .ok_or_else(|| "config file required, please supply using -c/--config or set the KLOI_CONFIG env var".to_string())?.to_string());
+    };
+
+    // load config and create client
+    // note: unwrap is fine here, since we've already checked if config is set above
+    let conf = config::load_config_from_file(config_path.unwrap())?;
+    let prefix = matches.get_one::<String>("prefix");
+
+    // other configs deploy to the same accounts and regions, only
+    // stacks tagged with the project of this config are considered
+    let project = conf.project.clone().ok_or_else(|| {
+        format!(
+            "orphans requires the config to set a project, add stacks.project(\"<name>\") to the config and apply the stacks to tag them with {}",
+            stacks::PROJECT_TAG
+        )
+    })?;
+
+    let mut orphans: Vec<Orphan> = Vec::new();
+    for region in conf.regions().iter() {
+        log::debug!("looking up orphaned stacks in region: {}", region);
+        for s in utils::list_stacks(region).await?.iter() {
+            let name = s.stack_name().unwrap_or_default();
+            let owned = match utils::stack_tag(s, stacks::PROJECT_TAG) {
+                Some(p) => p == project,
+                // stacks deployed before the project tag was
+                // added are only matched by their name prefix
+                None => prefix.is_some_and(|p| name.starts_with(p.as_str())),
+            };
+            if !owned {
+                continue;
+            }
+
+            let status = s.stack_status().map(|s| s.as_str()).unwrap_or_default();
+            if status.starts_with("DELETE_") {
+                continue;
+            }
+
+            // a stack is only known to the config in the region it is defined in
+            let defined = conf.stacks.iter().any(|c| {
+                c.name == name && c.region.clone().unwrap_or("eu-west-1".to_string()) == *region
+            });
+            if defined {
+                continue;
+            }
+
+            orphans.push(Orphan {
+                id: s.stack_id().unwrap_or_default().to_string(),
+                name: name.to_string(),
+                region: region.clone(),
+                status: status.to_lowercase(),
+            });
+        }
+    }
+
+    if orphans.is_empty() {
+        log::info!("no orphaned stacks found");
+        return Ok(());
+    }
+
+    for orphan in orphans.iter() {
+        human!(
+            "[{}] {} {}",
+            orphan.name.cyan(),
+            orphan.region.truecolor(96, 96, 96),
+            orphan.status.yellow()
+        );
+    }
+
+    if !matches.get_flag("delete") {
+        return Ok(());
+    }
+
+    let prompt = format!("delete {} orphaned stack(s)?", orphans.len());
+    if !utils::approve(&prompt, matches.get_flag("yes"))? {
+        log::info!("delete cancelled");
+        return Ok(());
+    }
+
+    // stacks importing the outputs of other orphans are deleted first
+    let mut imports = Vec::new();
+    for region in conf.regions().iter() {
+        let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+            .region(Region::new(region.clone()))
+            .load()
+            .await;
+        let client = aws_sdk_cloudformation::Client::new(&sdk_config);
+        imports.extend(orphan_imports(&client, region, &orphans).await?);
+    }
+    let orphans = delete_order(orphans, &imports);
+
+    for orphan in orphans.iter() {
+        let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+            .region(Region::new(orphan.region.clone()))
+            .load()
+            .await;
+        let client = aws_sdk_cloudformation::Client::new(&sdk_config);
+
+        let token = utils::request_token();
+        let res = client
+            .delete_stack()
+            .stack_name(orphan.name.clone())
+            .client_request_token(&token)
+            .send()
+            .await;
+
+        stack_request_result_handle!(res, orphan.name, "delete stack");
+
+        utils::stackprogress(
+            &client,
+            &orphan.name,
+            None,
+            orphan.region.clone(),
+            utils::WaitEvent::Delete,
+            Some(&token),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+// Import records that a stack imports an output exported by another
+// stack of the same region, which can't be deleted while it is imported
+#[derive(Debug, PartialEq)]
+struct Import {
+    region: String,
+    importer: String,
+    exporter: String,
+}
+
+// orphan_imports lists the imports between orphans of a region
+async fn orphan_imports(
+    client: &Client,
+    region: &str,
+    orphans: &[Orphan],
+) -> Result<Vec<Import>, String> {
+    let exports = client
+        .list_exports()
+        .into_paginator()
+        .items()
+        .send()
+        .collect::<Result<Vec<_>, _>>()
+        .await
+        .map_err(|e| format!("failed to list exports in {}: {}", region, e))?;
+
+    let mut imports = Vec::new();
+    for export in exports.iter() {
+        let Some(exporter) = orphans
+            .iter()
+            .find(|o| o.region == region && export.exporting_stack_id() == Some(o.id.as_str()))
+        else {
+            continue;
+        };
+        let Some(export_name) = export.name() else {
+            continue;
+        };
+
+        let importers = client
+            .list_imports()
+            .export_name(export_name)
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<Vec<_>, _>>()
+            .await;
+        let importers = match importers {
+            Ok(i) => i,
+            // exports nothing imports are reported as an error
+            Err(e) if e.message().is_some_and(|m| m.contains("is not imported")) => Vec::new(),
+            Err(e) => {
+                return Err(format!(
+                    "failed to list imports of export {}: {}",
+                    export_name,
+                    e.message().unwrap_or("unknown error")
+                ))
+            }
+        };
+
+        for importer in importers {
+            imports.push(Import {
+                region: region.to_string(),
+                importer,
+                exporter: exporter.name.clone(),
+            });
+        }
+    }
+
+    Ok(imports)
+}
+
+// delete_order sorts orphans so that a stack is deleted after every
+// orphan importing its outputs. Stacks are otherwise kept in listing order
+fn delete_order(mut orphans: Vec<Orphan>, imports: &[Import]) -> Vec<Orphan> {
+    let mut ordered = Vec::new();
+    while !orphans.is_empty() {
+        let imported = |o: &Orphan| {
+            imports.iter().any(|i| {
+                i.region == o.region
+                    && i.exporter == o.name
+                    && orphans
+                        .iter()
+                        .any(|other| other.region == i.region && other.name == i.importer)
+            })
+        };
+        // cloudformation prevents import cycles, the first stack is
+        // taken if one is reported anyway
+        let next = orphans.iter().position(|o| !imported(o)).unwrap_or(0);
+        ordered.push(orphans.remove(next));
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delete_order() {
+        let orphan = |name: &str| Orphan {
+            id: format!("arn:{}", name),
+            name: name.to_string(),
+            region: "eu-west-1".to_string(),
+            status: "create_complete".to_string(),
+        };
+        let import = |importer: &str, exporter: &str| Import {
+            region: "eu-west-1".to_string(),
+            importer: importer.to_string(),
+            exporter: exporter.to_string(),
+        };
+
+        let orphans = vec![
+            orphan("network"),
+            orphan("cache"),
+            orphan("db"),
+            orphan("app"),
+        ];
+        let imports = vec![
+            import("db", "network"),
+            import("app", "db"),
+            import("app", "network"),
+            // importers outside the orphans don't hold a stack back
+            import("other", "cache"),
+        ];
+
+        let names: Vec<String> = delete_order(orphans, &imports)
+            .into_iter()
+            .map(|o| o.name)
+            .collect();
+        assert_eq!(names, vec!["cache", "app", "db", "network"]);
+    }
+}
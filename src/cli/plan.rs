@@ -0,0 +1,732 @@
+use aws_config::{self, BehaviorVersion};
+use aws_sdk_cloudformation::error::ProvideErrorMetadata;
+use aws_sdk_cloudformation::types::{
+    ChangeAction, ChangeSetStatus, ChangeSetType, Replacement, ResourceChange, ResourceToImport,
+};
+use aws_sdk_cloudformation::Client;
+use aws_types::region::Region;
+use chrono::Utc;
+use clap::ArgMatches;
+use clap::{arg, Command};
+use colored::Colorize;
+use log;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokio::time::{sleep, Duration};
+
+use crate::apply::{self, Md5Sum, StackInput};
+use crate::config;
+use crate::output::{self, human};
+use crate::parameters;
+use crate::stacks;
+use crate::template;
+use crate::utils;
+
+const ABOUT: &str = r#"preview the resource changes apply would make to a stack,
+using a cloudformation change set
+"#;
+
+// directory saved plans are written to, relative to the working directory
+const PLANS_DIR: &str = ".kloi/plans";
+
+// SavedPlan is a change set kept by `plan --save` to be executed later.
+// It records the inputs the change set was created from so that
+// `execute` can refuse plans that no longer match the config
+#[derive(Serialize, Deserialize)]
+pub struct SavedPlan {
+    pub name: String,
+    pub stack: String,
+    pub region: String,
+    pub change_set_id: String,
+    // true when the change set creates a new stack
+    pub create: bool,
+    #[serde(flatten)]
+    pub fingerprint: Fingerprint,
+    pub created_at: String,
+}
+
+// Fingerprint is the part of the stack config a change set is created
+// from. It is computed from the local config only, parameters entered
+// at a prompt and tags set outside kloi are not part of it
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Fingerprint {
+    pub template_hash: String,
+    // NoEcho values are hashed
+    pub parameters: BTreeMap<String, String>,
+    pub capabilities: Vec<String>,
+    pub tags: BTreeMap<String, String>,
+    pub role_arn: Option<String>,
+    pub notification_arns: Vec<String>,
+}
+
+// ChangeSet is a change set created for a stack
+pub struct ChangeSet {
+    pub id: String,
+    pub stack_name: String,
+    // true when the change set creates a new stack
+    pub create: bool,
+    pub changes: Vec<ResourceChange>,
+}
+
+pub fn command() -> Command {
+    Command::new("plan")
+        .about(ABOUT.truecolor(125, 174, 189).to_string())
+        .arg(arg!([stack]))
+        .arg(
+            arg!(-s --save [NAME] "keep the change set and save the plan to execute later")
+                .num_args(0..=1)
+                .default_missing_value(""),
+        )
+        .arg(arg!(-c --config <FILE> "path to config file"))
+}
+
+pub async fn handle(matches: &ArgMatches) -> Result<(), String> {
+    let mut config_path = env::var("KLOI_CONFIG").ok();
+
+    // if config is not set by env, check if it is set by cli
+    if config_path.is_none() {
+        log::debug!("config path is not set by env, KLOI_CONFIG, check CLI -c/--config");
+        config_path = Some(matches
+            .get_one::<String>("config")
+            .ok_or_else(|| "config file required, please supply using -c/--config or set the KLOI_CONFIG env var".to_string())?.to_string());
+    };
+
+    // load config and create client
+    // note: unwrap is fine here, since we've already checked if config is set above
+    let conf = config::load_config_from_file(config_path.unwrap())?;
+    let stack_name: String = match matches.get_one::<String>("stack") {
+        Some(c) => {
+            if !conf.stacks.iter().any(|s| &s.name == c) {
+                Err(format!("stack [{}] not found", c))?;
+            };
+            c.to_string()
+        }
+        None => {
+            let opts = conf
+                .stacks
+                .iter()
+                .map(|s| s.name.clone())
+                .collect::<Vec<String>>();
+            utils::singleselect(opts, "select stack")
+        }
+    };
+
+    // can be unwrapped because we already checked that the stack exists
+    let mut stack = conf
+        .stacks
+        .iter()
+        .find(|s| s.name == stack_name)
+        .unwrap()
+        .clone();
+
+    // taken before prompting, execute compares it with the config alone
+    let fingerprint = Fingerprint::new(&stack)?;
+
+    apply::prompt_missing_parameters(&mut stack)?;
+    parameters::validate_stack(&stack)?;
+
+    let region = stack.region.clone().unwrap_or("eu-west-1".to_string());
+    let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+        .region(Region::new(region))
+        .load()
+        .await;
+    let client = aws_sdk_cloudformation::Client::new(&sdk_config);
+
+    let deployed = utils::describe_stack(&client, &stack.name).await?;
+    let exists = deployed.is_some();
+    let input = StackInput::new(&stack, &sdk_config, deployed.as_ref()).await?;
+
+    let Some(cs) = create_change_set(&client, &stack, &input, exists).await? else {
+        return Ok(());
+    };
+
+    print_changes(&cs);
+
+    if let Some(name) = matches.get_one::<String>("save") {
+        let name = match name.as_str() {
+            "" => format!("{}-{}", stack.name, Utc::now().format("%Y%m%d%H%M%S")),
+            n => n.to_string(),
+        };
+
+        let plan = SavedPlan {
+            name,
+            stack: stack.name.clone(),
+            region: stack.region.clone().unwrap_or("eu-west-1".to_string()),
+            change_set_id: cs.id.clone(),
+            create: cs.create,
+            fingerprint,
+            created_at: Utc::now().to_rfc3339(),
+        };
+
+        let path = plan.save()?;
+        log::info!(
+            "[{}] plan saved to {}, run `kloi execute {}` to apply it",
+            stack.name.cyan(),
+            path.green(),
+            plan.name
+        );
+        return Ok(());
+    }
+
+    // plans are previews, the change set is not kept
+    delete_change_set(&client, &cs).await
+}
+
+impl SavedPlan {
+    // path returns the file a plan with the given name is saved to
+    fn path(name: &str) -> PathBuf {
+        Path::new(PLANS_DIR).join(format!("{}.json", name))
+    }
+
+    // save writes the plan to the plans directory
+    pub fn save(&self) -> Result<String, String> {
+        self.save_in(Path::new(PLANS_DIR))
+    }
+
+    fn save_in(&self, dir: &Path) -> Result<String, String> {
+        let path = dir.join(format!("{}.json", self.name));
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("failed to create plans directory {}: {}", dir.display(), e))?;
+
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("failed to serialise plan: {}", e))?;
+        fs::write(&path, content)
+            .map_err(|e| format!("failed to write plan {}: {}", path.display(), e))?;
+
+        Ok(path.display().to_string())
+    }
+
+    // load reads a plan by name, or from a path to a plan file
+    pub fn load(plan: &str) -> Result<(SavedPlan, PathBuf), String> {
+        let path = match Path::new(plan).is_file() {
+            true => PathBuf::from(plan),
+            false => SavedPlan::path(plan),
+        };
+
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("failed to read plan [{}]: {}", plan, e))?;
+        let saved = serde_json::from_str(&content)
+            .map_err(|e| format!("failed to parse plan {}: {}", path.display(), e))?;
+
+        Ok((saved, path))
+    }
+
+    // stale returns why the plan no longer matches the stack config,
+    // if it doesn't
+    pub fn stale(&self, s: &stacks::Stack) -> Result<Option<&'static str>, String> {
+        let region = s.region.clone().unwrap_or("eu-west-1".to_string());
+        if region != self.region {
+            return Ok(Some("the stack region changed"));
+        }
+
+        Ok(self.fingerprint.changed(&Fingerprint::new(s)?))
+    }
+}
+
+impl Fingerprint {
+    // new fingerprints the stack config, rendering the template
+    // without prompting for parameters or calling AWS
+    pub fn new(s: &stacks::Stack) -> Result<Self, String> {
+        let template = s.generate_template()?;
+        let declared = template::parse(&template)
+            .map(|t| template::parameters(&t))
+            .unwrap_or_default();
+
+        let values = match &s.parameters {
+            Some(p) => {
+                parameters::resolve(p, &template).map_err(|e| format!("[{}] {}", s.name, e))?
+            }
+            None => HashMap::new(),
+        };
+        let mut params: BTreeMap<String, String> = values
+            .into_iter()
+            .map(|(k, v)| {
+                let v = match declared.get(&k) {
+                    Some(d) if d.no_echo => format!("md5:{}", v.md5()),
+                    _ => v,
+                };
+                (k, v)
+            })
+            .collect();
+        for k in s.previous_parameters.iter().flatten() {
+            params.insert(k.clone(), "UsePreviousValue".to_string());
+        }
+
+        let mut capabilities = s.capabilities.clone().unwrap_or_default();
+        capabilities.sort();
+        let mut notification_arns = s.notification_arns.clone().unwrap_or_default();
+        notification_arns.sort();
+
+        Ok(Fingerprint {
+            template_hash: template.md5(),
+            parameters: params,
+            capabilities,
+            tags: s.tags().into_iter().collect(),
+            role_arn: s.role_arn.clone(),
+            notification_arns,
+        })
+    }
+
+    // changed returns the first input that differs between two fingerprints
+    pub fn changed(&self, current: &Fingerprint) -> Option<&'static str> {
+        if self.template_hash != current.template_hash {
+            Some("the template changed")
+        } else if self.parameters != current.parameters {
+            Some("the parameters changed")
+        } else if self.capabilities != current.capabilities {
+            Some("the capabilities changed")
+        } else if self.tags != current.tags {
+            Some("the tags changed")
+        } else if self.role_arn != current.role_arn {
+            Some("the role arn changed")
+        } else if self.notification_arns != current.notification_arns {
+            Some("the notification arns changed")
+        } else {
+            None
+        }
+    }
+}
+
+// create_change_set creates a change set from the same inputs used by
+// stack create/update requests and waits for it to be ready.
+// None is returned (and the change set removed) if there are no changes
+pub async fn create_change_set(
+    client: &Client,
+    s: &stacks::Stack,
+    input: &StackInput,
+    exists: bool,
+) -> Result<Option<ChangeSet>, String> {
+    let change_set_type = if exists {
+        ChangeSetType::Update
+    } else {
+        ChangeSetType::Create
+    };
+
+    request_change_set(client, s, input, exists, change_set_type, None).await
+}
+
+// create_import_change_set creates a change set importing existing
+// resources into a stack, which is created if it doesn't exist yet
+pub async fn create_import_change_set(
+    client: &Client,
+    s: &stacks::Stack,
+    input: &StackInput,
+    exists: bool,
+    resources: Vec<ResourceToImport>,
+) -> Result<Option<ChangeSet>, String> {
+    request_change_set(
+        client,
+        s,
+        input,
+        exists,
+        ChangeSetType::Import,
+        Some(resources),
+    )
+    .await
+}
+
+async fn request_change_set(
+    client: &Client,
+    s: &stacks::Stack,
+    input: &StackInput,
+    exists: bool,
+    change_set_type: ChangeSetType,
+    resources_to_import: Option<Vec<ResourceToImport>>,
+) -> Result<Option<ChangeSet>, String> {
+    let name = format!("kloi-{}", Utc::now().format("%Y%m%d%H%M%S"));
+    let res = client
+        .create_change_set()
+        .stack_name(&s.name)
+        .change_set_name(&name)
+        .change_set_type(change_set_type)
+        .set_resources_to_import(resources_to_import)
+        .set_template_body(input.template_body())
+        .set_template_url(input.template_url.clone())
+        .set_parameters(Some(input.parameters.clone()))
+        .set_capabilities(Some(input.capabilities.clone()))
+        .set_tags(Some(input.tags.clone()))
+        .set_role_arn(s.role_arn.clone())
+        .set_notification_arns(s.notification_arns.clone())
+        .send()
+        .await;
+
+    let res = match res {
+        Ok(res) => res,
+        Err(e) if e.message().is_some_and(utils::no_updates) => {
+            log::info!("[{}] {}", s.name.cyan(), "no changes".green());
+            return Ok(None);
+        }
+        Err(e) => {
+            return Err(format!(
+                "[{}] error occurred during create change set request: {} - {}",
+                s.name,
+                e.code().unwrap_or("no error code"),
+                e.message().unwrap_or("unknown error")
+            ))
+        }
+    };
+
+    let mut cs = ChangeSet {
+        id: res.id().unwrap_or(&name).to_string(),
+        stack_name: s.name.clone(),
+        create: !exists,
+        changes: Vec::new(),
+    };
+
+    log::info!(
+        "[{}] {} {}",
+        s.name.cyan(),
+        "creating change set".green(),
+        name.truecolor(96, 96, 96)
+    );
+
+    // wait for the change set to be created
+    loop {
+        let res = client
+            .describe_change_set()
+            .change_set_name(&cs.id)
+            .send()
+            .await
+            .map_err(|e| {
+                format!(
+                    "[{}] error describing change set: {}",
+                    s.name,
+                    e.message().unwrap_or("unknown error")
+                )
+            })?;
+
+        match res.status() {
+            Some(ChangeSetStatus::CreateComplete) => break,
+            Some(ChangeSetStatus::Failed) => {
+                let reason = res.status_reason().unwrap_or("unknown reason").to_string();
+                if utils::no_updates(&reason) {
+                    log::info!("[{}] {}", s.name.cyan(), "no changes".green());
+                    delete_change_set(client, &cs).await?;
+                    return Ok(None);
+                }
+
+                delete_change_set(client, &cs).await?;
+                return Err(format!("[{}] change set failed: {}", s.name, reason));
+            }
+            _ => sleep(Duration::from_secs(2)).await,
+        }
+    }
+
+    cs.changes = change_set_changes(client, &cs.id).await?;
+    Ok(Some(cs))
+}
+
+// change_set_changes returns every resource change of a change set
+pub async fn change_set_changes(client: &Client, id: &str) -> Result<Vec<ResourceChange>, String> {
+    let mut changes = Vec::new();
+    let mut next_token: Option<String> = None;
+    loop {
+        let res = client
+            .describe_change_set()
+            .change_set_name(id)
+            .set_next_token(next_token)
+            .send()
+            .await
+            .map_err(|e| {
+                format!(
+                    "error describing change set: {}",
+                    e.message().unwrap_or("unknown error")
+                )
+            })?;
+
+        changes.extend(
+            res.changes()
+                .iter()
+                .filter_map(|c| c.resource_change().cloned()),
+        );
+
+        next_token = res.next_token().map(|t| t.to_string());
+        if next_token.is_none() {
+            break;
+        }
+    }
+
+    Ok(changes)
+}
+
+// execute_change_set executes a change set and waits for the stack.
+// false is returned if cloudformation had nothing to update
+pub async fn execute_change_set(
+    client: &Client,
+    s: &stacks::Stack,
+    cs: &ChangeSet,
+) -> Result<bool, String> {
+    let token = utils::request_token();
+    let res = client
+        .execute_change_set()
+        .change_set_name(&cs.id)
+        .client_request_token(&token)
+        .send()
+        .await;
+
+    // cloudformation reports change sets with nothing to update as an error
+    if let Err(e) = &res {
+        if e.message().is_some_and(utils::no_updates) {
+            log::info!(
+                "[{}] {}",
+                s.name.cyan(),
+                "no updates to be performed".green()
+            );
+            return Ok(false);
+        }
+    }
+    utils::stack_request_result_handle!(res, s.name, "execute change set");
+
+    utils::stackprogress(
+        client,
+        &s.name,
+        s.custom_resources.clone(),
+        s.region.clone().unwrap_or("eu-west-1".to_string()),
+        if cs.create {
+            utils::WaitEvent::Create
+        } else {
+            utils::WaitEvent::Update
+        },
+        Some(&token),
+    )
+    .await?;
+
+    Ok(true)
+}
+
+// delete_change_set removes a change set. Change sets that create a stack
+// leave the stack in REVIEW_IN_PROGRESS, so the stack is removed as well
+pub async fn delete_change_set(client: &Client, cs: &ChangeSet) -> Result<(), String> {
+    let err = |e: String| format!("[{}] error deleting change set: {}", cs.stack_name, e);
+    if cs.create {
+        return client
+            .delete_stack()
+            .stack_name(&cs.stack_name)
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| err(e.message().unwrap_or("unknown error").to_string()));
+    }
+
+    client
+        .delete_change_set()
+        .change_set_name(&cs.id)
+        .send()
+        .await
+        .map(|_| ())
+        .map_err(|e| err(e.message().unwrap_or("unknown error").to_string()))
+}
+
+// check_replacements returns an error listing the protected
+// resources (see Stack::is_protected) the change set would replace
+pub fn check_replacements(s: &stacks::Stack, cs: &ChangeSet) -> Result<(), String> {
+    let replaced: Vec<String> = cs
+        .changes
+        .iter()
+        .filter(|c| c.replacement() == Some(&Replacement::True))
+        .filter(|c| c.resource_type().is_some_and(|t| s.is_protected(t)))
+        .map(|c| {
+            format!(
+                "{} ({})",
+                c.logical_resource_id().unwrap_or("-"),
+                c.resource_type().unwrap_or("-")
+            )
+        })
+        .collect();
+
+    if replaced.is_empty() {
+        return Ok(());
+    }
+
+    Err(format!(
+        "[{}] aborting, the change set replaces protected resources: {}. Use --allow-replacement to apply it anyway",
+        s.name,
+        replaced.join(", ")
+    ))
+}
+
+// print_changes prints the resource changes of a change set as a table
+pub fn print_changes(cs: &ChangeSet) {
+    if cs.changes.is_empty() {
+        log::info!("[{}] {}", cs.stack_name.cyan(), "no changes".green());
+        return;
+    }
+
+    let header = [
+        "action",
+        "logical id",
+        "type",
+        "replacement",
+        "scope",
+        "caused by",
+    ];
+
+    let rows: Vec<[String; 6]> = cs
+        .changes
+        .iter()
+        .map(|c| {
+            let replacement = match c.replacement() {
+                Some(Replacement::True) => "True",
+                Some(Replacement::Conditional) => "Conditional",
+                _ => "-",
+            };
+
+            let scope = c
+                .scope()
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<&str>>()
+                .join(",");
+
+            let mut caused_by: Vec<String> = Vec::new();
+            for d in c.details() {
+                let entity = match (d.causing_entity(), d.change_source()) {
+                    (Some(e), _) => e.to_string(),
+                    (None, Some(s)) => s.as_str().to_string(),
+                    _ => continue,
+                };
+                if !caused_by.contains(&entity) {
+                    caused_by.push(entity);
+                }
+            }
+
+            [
+                c.action().map(|a| a.as_str()).unwrap_or("-").to_string(),
+                c.logical_resource_id().unwrap_or("-").to_string(),
+                c.resource_type().unwrap_or("-").to_string(),
+                replacement.to_string(),
+                if scope.is_empty() {
+                    "-".to_string()
+                } else {
+                    scope
+                },
+                if caused_by.is_empty() {
+                    "-".to_string()
+                } else {
+                    caused_by.join(", ")
+                },
+            ]
+        })
+        .collect();
+
+    let lines = output::table(&header, &rows, |r, i, cell| {
+        let col = rows[r][i].as_str();
+        let cell = match i {
+            0 => match ChangeAction::from(col) {
+                ChangeAction::Add | ChangeAction::Import => cell.green(),
+                ChangeAction::Remove => cell.red(),
+                _ => cell.yellow(),
+            },
+            1 => cell.cyan(),
+            3 if col == "True" => cell.red().bold(),
+            3 if col == "Conditional" => cell.yellow(),
+            _ => cell.truecolor(96, 96, 96),
+        };
+        cell.to_string()
+    });
+
+    human!("[{}] change set:", cs.stack_name.cyan());
+    for line in lines.iter() {
+        human!("{}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    const TEMPLATE: &str = r#"
+Parameters:
+  Env:
+    Type: String
+  Password:
+    Type: String
+    NoEcho: true
+Resources:
+  Topic:
+    Type: AWS::SNS::Topic
+"#;
+
+    fn load_stack(config: &str) -> stacks::Stack {
+        let mut stack = config::load_test_config(config).stacks.remove(0);
+        stack.template = TEMPLATE.to_string();
+        stack
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let stack = load_stack(
+            r#"stacks.add(stacks.new(name = "app", region = "eu-west-1", template = "none", inputs = stacks.inputs(parameters = {"Env": "prod", "Password": "secret"}), options = stacks.options(capabilities = ["CAPABILITY_IAM"], tags = {"team": "platform"})))"#,
+        );
+        let fingerprint = Fingerprint::new(&stack).unwrap();
+
+        assert_eq!(fingerprint.template_hash, TEMPLATE.to_string().md5());
+        assert_eq!(fingerprint.parameters.get("Env").unwrap(), "prod");
+        assert_eq!(
+            fingerprint.parameters.get("Password").unwrap(),
+            &format!("md5:{}", "secret".to_string().md5())
+        );
+        assert_eq!(fingerprint.capabilities, vec!["CAPABILITY_IAM"]);
+        assert_eq!(fingerprint.tags.get("team").unwrap(), "platform");
+        assert_eq!(
+            fingerprint.changed(&Fingerprint::new(&stack).unwrap()),
+            None
+        );
+
+        let mut changed = stack.clone();
+        changed.capabilities = None;
+        assert_eq!(
+            fingerprint.changed(&Fingerprint::new(&changed).unwrap()),
+            Some("the capabilities changed")
+        );
+
+        let mut changed = stack.clone();
+        changed.role_arn = Some("arn:aws:iam::123456789012:role/deploy".to_string());
+        assert_eq!(
+            fingerprint.changed(&Fingerprint::new(&changed).unwrap()),
+            Some("the role arn changed")
+        );
+
+        let mut changed = stack.clone();
+        changed.notification_arns = Some(vec!["arn:aws:sns:eu-west-1:123456789012:ops".into()]);
+        assert_eq!(
+            fingerprint.changed(&Fingerprint::new(&changed).unwrap()),
+            Some("the notification arns changed")
+        );
+    }
+
+    #[test]
+    fn test_saved_plan() {
+        let stack = load_stack(
+            r#"stacks.add(stacks.new(name = "app", region = "eu-west-1", template = "none", inputs = stacks.inputs(parameters = {"Env": "prod"})))"#,
+        );
+        let plan = SavedPlan {
+            name: "release".to_string(),
+            stack: stack.name.clone(),
+            region: "eu-west-1".to_string(),
+            change_set_id: "arn:aws:cloudformation:eu-west-1:123456789012:changeSet/kloi/1"
+                .to_string(),
+            create: false,
+            fingerprint: Fingerprint::new(&stack).unwrap(),
+            created_at: Utc::now().to_rfc3339(),
+        };
+
+        let dir = TempDir::new("kloi").unwrap();
+        let path = plan.save_in(dir.path()).unwrap();
+        let (loaded, loaded_path) = SavedPlan::load(&path).unwrap();
+        assert_eq!(loaded_path, PathBuf::from(&path));
+        assert_eq!(loaded.change_set_id, plan.change_set_id);
+        assert_eq!(loaded.fingerprint, plan.fingerprint);
+        assert_eq!(loaded.stale(&stack).unwrap(), None);
+
+        // a plan without a complete fingerprint can't be checked
+        let old = r#"{"name": "old", "stack": "app", "region": "eu-west-1", "change_set_id": "id", "create": false, "template_hash": "abc", "parameters": {}, "created_at": ""}"#;
+        let old_path = dir.path().join("old.json");
+        fs::write(&old_path, old).unwrap();
+        assert!(SavedPlan::load(&old_path.display().to_string()).is_err());
+    }
+}
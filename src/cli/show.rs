@@ -1,5 +1,8 @@
 use crate::config;
+use crate::output;
+use crate::stacks;
 use crate::utils;
+use crate::values;
 use clap::ArgMatches;
 use clap::{arg, Command};
 use colored::Colorize;
@@ -17,6 +20,7 @@ pub fn command() -> Command {
     Command::new("show")
         .about(ABOUT.truecolor(125, 174, 189).to_string())
         .arg(arg!([stack]))
+        .arg(arg!(--"explain-values" "show the values file each template value came from"))
         .arg(arg!(-c --config <FILE> "path to config file"))
 }
 
@@ -55,11 +59,32 @@ pub async fn handle(matches: &ArgMatches) -> Result<(), String> {
     let ps = SyntaxSet::load_defaults_newlines();
     let ts = ThemeSet::load_defaults();
 
+    let mut report = output::Report::new("show");
     for stack in &conf.stacks {
         if stack.name.as_str() != stack_name.as_str() {
             continue;
         }
 
+        if !output::is_text() {
+            let mut result = output::StackResult {
+                name: stack.name.clone(),
+                region: stack.region.clone().unwrap_or("eu-west-1".to_string()),
+                ..Default::default()
+            };
+            if matches.get_flag("explain-values") {
+                result.values = Some(value_sources(stack));
+            } else {
+                result.template = Some(stack.generate_template()?);
+            }
+            report.stacks.push(result);
+            continue;
+        }
+
+        if matches.get_flag("explain-values") {
+            explain_values(stack);
+            continue;
+        }
+
         let template = stack.generate_template()?;
         let syntax = ps.find_syntax_by_name("YAML").unwrap();
 
@@ -74,5 +99,44 @@ pub async fn handle(matches: &ArgMatches) -> Result<(), String> {
         println!("");
     }
 
-    Ok(())
+    report.emit(&Ok(()))
+}
+
+// explain_values prints every values key along with
+// the values layer (file or inline) it was taken from
+fn explain_values(stack: &stacks::Stack) {
+    let sources = value_sources(stack);
+    if sources.is_empty() {
+        log::info!("[{}] no values defined", stack.name.cyan());
+        return;
+    }
+
+    for v in sources {
+        println!(
+            "{} = {} {}",
+            v.key.cyan(),
+            v.value,
+            format!("[{}]", v.source).truecolor(96, 96, 96)
+        );
+    }
+}
+
+// value_sources returns every values key sorted, along with its
+// value and the values layer it was taken from
+fn value_sources(stack: &stacks::Stack) -> Vec<output::ValueSource> {
+    let (Some(values), Some(sources)) = (&stack.values, &stack.value_sources) else {
+        return Vec::new();
+    };
+
+    let mut keys: Vec<&String> = sources.keys().collect();
+    keys.sort();
+    keys.into_iter()
+        .map(|key| output::ValueSource {
+            key: key.clone(),
+            value: values::lookup(values, key)
+                .cloned()
+                .unwrap_or(serde_json::Value::Null),
+            source: sources[key].clone(),
+        })
+        .collect()
 }
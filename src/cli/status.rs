@@ -1,10 +1,17 @@
 use crate::config;
+use crate::drift;
+use crate::output;
+use crate::stacks;
+use crate::template;
 use crate::utils;
 use aws_config::{self, BehaviorVersion};
+use aws_sdk_cloudformation::types::{Stack, StackDriftStatus};
+use aws_smithy_types::DateTime;
 use aws_types::region::Region;
 use clap::ArgMatches;
 use clap::{arg, Command};
-use colored::Colorize;
+use colored::{ColoredString, Colorize};
+use futures::future::join_all;
 use log;
 use std::env;
 use utils::exec_jobs;
@@ -16,6 +23,9 @@ pub fn command() -> Command {
     Command::new("status")
         .about(ABOUT.truecolor(125, 174, 189).to_string())
         .arg(arg!([stack]))
+        .arg(arg!(-A --all ... "show the status of all stacks"))
+        .arg(arg!(-t --table "show an aligned table instead of the detailed view"))
+        .arg(arg!(--drift "detect drift and show the drift status of each stack"))
         .arg(arg!(-c --config <FILE> "path to config file"))
 }
 
@@ -33,58 +43,498 @@ pub async fn handle(matches: &ArgMatches) -> Result<(), String> {
     // load config and create client
     // note: unwrap is fine here, since we've already checked if config is set above
     let conf = config::load_config_from_file(config_path.unwrap())?;
-    for stack in &conf.stacks {
-        // execute on_status hook
+    let status_all = matches.get_one::<u8>("all").unwrap_or(&0);
+    let selected_stacks: Vec<&stacks::Stack> = if *status_all == 1 {
+        conf.stacks.iter().collect()
+    } else {
+        match matches.get_one::<String>("stack") {
+            Some(c) => {
+                let stack = conf
+                    .stacks
+                    .iter()
+                    .find(|s| &s.name == c)
+                    .ok_or_else(|| format!("stack [{}] not found", c))?;
+                vec![stack]
+            }
+            None => {
+                // if no stack is specified, use interactive form
+                let opts = conf
+                    .stacks
+                    .iter()
+                    .map(|s| s.name.clone())
+                    .collect::<Vec<String>>();
+                let selected = utils::multiselect(opts, "select stack");
+                conf.stacks
+                    .iter()
+                    .filter(|s| selected.contains(&s.name))
+                    .collect()
+            }
+        }
+    };
+
+    if selected_stacks.is_empty() {
+        return Err("no stacks found".to_string());
+    }
+
+    // execute on_status hooks
+    for stack in selected_stacks.iter() {
         exec_jobs!(on_status, &stack, stack.name.clone(), false);
+    }
 
-        // // create client per stack
-        let region = stack.region.clone().unwrap_or("eu-west-1".to_string());
-        let sdk_config = aws_config::defaults(BehaviorVersion::latest())
-            .region(Region::new(region))
-            .load()
-            .await;
-        let client = aws_sdk_cloudformation::Client::new(&sdk_config);
-
-        // get stack status
-        let res = client
-            .describe_stacks()
-            .stack_name(stack.name.clone())
-            .send()
-            .await;
-
-        match res {
-            Ok(r) => {
-                let s = r.stacks.unwrap().pop().unwrap();
-
-                println!(
-                    "[{}] {}",
-                    s.stack_name.unwrap().cyan(),
-                    s.stack_status.unwrap().as_str().to_lowercase().green()
-                );
-            }
-            Err(e) => {
-                let err = if let Some(message) = e.into_service_error().meta().message() {
-                    format!(
+    // fetch all stacks concurrently, results keep the selection order
+    let detect_drift = matches.get_flag("drift");
+    let results = join_all(
+        selected_stacks
+            .iter()
+            .map(|stack| fetch_status(stack, detect_drift)),
+    )
+    .await;
+
+    let mut statuses = Vec::new();
+    let mut errors = Vec::new();
+    for result in results {
+        match result {
+            Ok(status) => statuses.push(status),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    if !output::is_text() {
+        let mut report = output::Report::new("status");
+        report.stacks = statuses.iter().map(stack_result).collect();
+        let result = match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors.join("\n")),
+        };
+        report.emit(&result)?;
+    } else if matches.get_flag("table") {
+        print_table(&statuses);
+    } else {
+        for status in statuses.iter() {
+            print_details(status);
+        }
+    }
+
+    // execute post jobs
+    for stack in selected_stacks.iter() {
+        exec_jobs!(on_status, &stack, stack.name.clone(), true);
+    }
+
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+
+    Ok(())
+}
+
+// StackStatus is the deployed state of a stack from the config,
+// stack is None if the stack does not exist
+struct StackStatus {
+    name: String,
+    region: String,
+    stack: Option<Stack>,
+    // result of drift detection, when requested
+    drift: Option<StackDriftStatus>,
+    // parameters declared as NoEcho by the local template
+    no_echo: Vec<String>,
+}
+
+// fetch_status describes a stack and optionally runs drift detection
+async fn fetch_status(stack: &stacks::Stack, detect_drift: bool) -> Result<StackStatus, String> {
+    // create client per stack
+    let region = stack.region.clone().unwrap_or("eu-west-1".to_string());
+    let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+        .region(Region::new(region.clone()))
+        .load()
+        .await;
+    let client = aws_sdk_cloudformation::Client::new(&sdk_config);
+
+    let no_echo = stack
+        .generate_template()
+        .and_then(|t| template::parse(&t))
+        .map(|t| {
+            template::parameters(&t)
+                .into_iter()
+                .filter(|(_, p)| p.no_echo)
+                .map(|(k, _)| k)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut status = StackStatus {
+        name: stack.name.clone(),
+        region,
+        stack: None,
+        drift: None,
+        no_echo,
+    };
+
+    // get stack status
+    let res = client
+        .describe_stacks()
+        .stack_name(stack.name.clone())
+        .send()
+        .await;
+
+    match res {
+        Ok(r) => status.stack = r.stacks().first().cloned(),
+        Err(e) => {
+            let message = e
+                .into_service_error()
+                .meta()
+                .message()
+                .map(|m| m.to_string());
+            match message {
+                Some(m) if m.contains("does not exist") => return Ok(status),
+                Some(m) => {
+                    return Err(format!(
                         "[{}] error occured while getting stack status: {}",
                         stack.name,
-                        message.red()
-                    )
-                } else {
-                    format!("[{}] {}", stack.name.cyan(), "unknown error".red())
-                };
+                        m.red()
+                    ))
+                }
+                None => return Err(format!("[{}] {}", stack.name.cyan(), "unknown error".red())),
+            }
+        }
+    }
+
+    if detect_drift && status.stack.is_some() {
+        status.drift = Some(drift::detect_drift(&client, &stack.name).await?);
+    }
 
-                if err.contains("does not exist") {
-                    println!("[{}] {}", stack.name.cyan(), "does not exist".yellow());
-                    return Ok(());
+    Ok(status)
+}
+
+// drift_status returns the drift detected by --drift, or the
+// result of the last drift detection recorded on the stack
+fn drift_status(status: &StackStatus) -> Option<StackDriftStatus> {
+    status.drift.clone().or_else(|| {
+        status
+            .stack
+            .as_ref()?
+            .drift_information()?
+            .stack_drift_status()
+            .cloned()
+    })
+}
+
+// stack_result converts a stack status to its report entry
+fn stack_result(status: &StackStatus) -> output::StackResult {
+    let mut result = output::StackResult {
+        name: status.name.clone(),
+        region: status.region.clone(),
+        ..Default::default()
+    };
+
+    let Some(s) = &status.stack else {
+        result.status = Some("does_not_exist".to_string());
+        return result;
+    };
+
+    result.status = s.stack_status().map(|s| s.as_str().to_lowercase());
+    result.status_reason = s.stack_status_reason().map(|r| r.to_string());
+    result.last_updated = s
+        .last_updated_time()
+        .or(s.creation_time())
+        .and_then(|t| chrono::DateTime::from_timestamp(t.secs(), t.subsec_nanos()))
+        .map(|t| t.to_rfc3339());
+    result.termination_protection = Some(s.enable_termination_protection().unwrap_or(false));
+    result.drift = drift_status(status).map(|d| d.as_str().to_lowercase());
+    result.parameters = Some(parameters(status).into_iter().collect());
+    result.outputs = Some(
+        s.outputs()
+            .iter()
+            .map(|o| output::StackOutput {
+                key: o.output_key().unwrap_or_default().to_string(),
+                value: o.output_value().unwrap_or_default().to_string(),
+                description: o.description().map(|d| d.to_string()),
+                export_name: o.export_name().map(|e| e.to_string()),
+            })
+            .collect(),
+    );
+
+    result
+}
+
+// parameters returns the deployed parameters of a stack sorted by
+// key, values of NoEcho parameters are masked
+fn parameters(status: &StackStatus) -> Vec<(String, String)> {
+    let Some(s) = &status.stack else {
+        return Vec::new();
+    };
+
+    let mut params: Vec<(String, String)> = s
+        .parameters()
+        .iter()
+        .map(|p| {
+            let key = p.parameter_key().unwrap_or("-").to_string();
+            let value = if status.no_echo.contains(&key) {
+                "****".to_string()
+            } else {
+                p.parameter_value().unwrap_or_default().to_string()
+            };
+            (key, value)
+        })
+        .collect();
+    params.sort();
+    params
+}
+
+fn colored_stack_status(s: &Stack) -> ColoredString {
+    let status = s
+        .stack_status()
+        .map(|s| s.as_str().to_lowercase())
+        .unwrap_or("-".to_string());
+
+    if status.contains("failed") || status.contains("rollback") {
+        status.red()
+    } else if status.ends_with("in_progress") {
+        status.yellow()
+    } else {
+        status.green()
+    }
+}
+
+// format_time renders a cloudformation timestamp for display
+pub fn format_time(t: Option<&DateTime>) -> String {
+    t.and_then(|t| chrono::DateTime::from_timestamp(t.secs(), t.subsec_nanos()))
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or("-".to_string())
+}
+
+fn last_updated(s: &Stack) -> String {
+    format_time(s.last_updated_time().or(s.creation_time()))
+}
+
+fn termination_protection(s: &Stack) -> &'static str {
+    if s.enable_termination_protection().unwrap_or(false) {
+        "enabled"
+    } else {
+        "disabled"
+    }
+}
+
+// print_details prints the status, drift, parameters and outputs of a stack
+fn print_details(status: &StackStatus) {
+    let Some(s) = &status.stack else {
+        println!("[{}] {}", status.name.cyan(), "does not exist".yellow());
+        return;
+    };
+
+    println!("[{}] {}", status.name.cyan(), colored_stack_status(s));
+
+    let label = |l: &str| format!("{:<24}", l).truecolor(96, 96, 96);
+    println!("  {}{}", label("region"), status.region);
+    println!("  {}{}", label("last updated"), last_updated(s));
+    if let Some(reason) = s.stack_status_reason() {
+        println!("  {}{}", label("status reason"), reason);
+    }
+    println!(
+        "  {}{}",
+        label("termination protection"),
+        termination_protection(s)
+    );
+
+    let drift = match drift_status(status) {
+        Some(d) => {
+            let checked = s.drift_information().and_then(|d| d.last_check_timestamp());
+            match checked {
+                Some(_) if status.drift.is_none() => {
+                    format!(
+                        "{} (checked {})",
+                        drift::colored_status(&d),
+                        format_time(checked)
+                    )
                 }
+                _ => drift::colored_status(&d).to_string(),
+            }
+        }
+        None => "-".to_string(),
+    };
+    println!("  {}{}", label("drift"), drift);
+
+    if !s.parameters().is_empty() {
+        println!("  {}", "parameters".bold());
+        for (key, value) in parameters(status) {
+            println!("    {} = {}", key, value);
+        }
+    }
+
+    if !s.outputs().is_empty() {
+        println!("  {}", "outputs".bold());
+        let mut outputs: Vec<_> = s.outputs().iter().collect();
+        outputs.sort_by(|a, b| a.output_key().cmp(&b.output_key()));
+        for o in outputs {
+            let export = o
+                .export_name()
+                .map(|e| {
+                    format!(" [export: {}]", e)
+                        .truecolor(96, 96, 96)
+                        .to_string()
+                })
+                .unwrap_or_default();
+            println!(
+                "    {} = {}{}",
+                o.output_key().unwrap_or("-"),
+                o.output_value().unwrap_or_default(),
+                export
+            );
+        }
+    }
+}
+
+// print_table prints one aligned row per stack
+fn print_table(statuses: &[StackStatus]) {
+    let header = [
+        "stack",
+        "region",
+        "status",
+        "last updated",
+        "drift",
+        "protection",
+        "reason",
+    ];
 
-                Err(err)?
+    let rows: Vec<[String; 7]> = statuses
+        .iter()
+        .map(|status| match &status.stack {
+            Some(s) => [
+                status.name.clone(),
+                status.region.clone(),
+                s.stack_status()
+                    .map(|s| s.as_str().to_lowercase())
+                    .unwrap_or("-".to_string()),
+                last_updated(s),
+                drift_status(status)
+                    .map(|d| d.as_str().to_lowercase())
+                    .unwrap_or("-".to_string()),
+                termination_protection(s).to_string(),
+                s.stack_status_reason().unwrap_or("-").to_string(),
+            ],
+            None => [
+                status.name.clone(),
+                status.region.clone(),
+                "does not exist".to_string(),
+                "-".to_string(),
+                "-".to_string(),
+                "-".to_string(),
+                "-".to_string(),
+            ],
+        })
+        .collect();
+
+    let lines = output::table(&header, &rows, |r, i, cell| {
+        let status = &statuses[r];
+        match (i, &status.stack) {
+            (0, _) => cell.cyan().to_string(),
+            (2, None) => cell.yellow().to_string(),
+            (2, Some(s)) => {
+                // keep the padding, color only the status text
+                let colored = colored_stack_status(s).to_string();
+                cell.replacen(rows[r][i].as_str(), &colored, 1)
             }
+            (4, _) => match drift_status(status) {
+                Some(StackDriftStatus::Drifted) => cell.red().to_string(),
+                Some(StackDriftStatus::InSync) => cell.green().to_string(),
+                _ => cell.truecolor(96, 96, 96).to_string(),
+            },
+            _ => cell,
         }
+    });
 
-        // execute post jobs
-        exec_jobs!(on_status, &stack, stack.name.clone(), true);
+    for line in lines.iter() {
+        println!("{}", line);
     }
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_cloudformation::types::{Output, Parameter, StackStatus as CfnStackStatus};
+
+    fn status(stack: Option<Stack>, no_echo: &[&str]) -> StackStatus {
+        StackStatus {
+            name: "network".to_string(),
+            region: "eu-west-1".to_string(),
+            stack,
+            drift: None,
+            no_echo: no_echo.iter().map(|k| k.to_string()).collect(),
+        }
+    }
+
+    fn deployed() -> Stack {
+        Stack::builder()
+            .stack_name("network")
+            .stack_status(CfnStackStatus::UpdateComplete)
+            .creation_time(DateTime::from_secs(1700000000))
+            .enable_termination_protection(true)
+            .parameters(
+                Parameter::builder()
+                    .parameter_key("VpcCidr")
+                    .parameter_value("10.0.0.0/16")
+                    .build(),
+            )
+            .parameters(
+                Parameter::builder()
+                    .parameter_key("DbPassword")
+                    .parameter_value("hunter2")
+                    .build(),
+            )
+            .outputs(
+                Output::builder()
+                    .output_key("VpcId")
+                    .output_value("vpc-123")
+                    .export_name("network-vpc")
+                    .build(),
+            )
+            .build()
+    }
+
+    #[test]
+    fn test_missing_stack_result() {
+        let result = stack_result(&status(None, &[]));
+        assert_eq!(result.name, "network");
+        assert_eq!(result.region, "eu-west-1");
+        assert_eq!(result.status, Some("does_not_exist".to_string()));
+        assert!(result.parameters.is_none());
+        assert!(result.outputs.is_none());
+        assert!(parameters(&status(None, &[])).is_empty());
+    }
+
+    #[test]
+    fn test_stack_result() {
+        let status = status(Some(deployed()), &["DbPassword"]);
+        let result = stack_result(&status);
+        assert_eq!(result.status, Some("update_complete".to_string()));
+        assert_eq!(result.termination_protection, Some(true));
+        assert_eq!(
+            result.last_updated,
+            Some("2023-11-14T22:13:20+00:00".to_string())
+        );
+        assert!(result.drift.is_none());
+
+        let params = result.parameters.unwrap();
+        assert_eq!(params.get("DbPassword"), Some(&"****".to_string()));
+        assert_eq!(params.get("VpcCidr"), Some(&"10.0.0.0/16".to_string()));
+
+        let outputs = result.outputs.unwrap();
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].key, "VpcId");
+        assert_eq!(outputs[0].export_name, Some("network-vpc".to_string()));
+    }
+
+    #[test]
+    fn test_parameters() {
+        let masked = status(Some(deployed()), &["DbPassword"]);
+        assert_eq!(
+            parameters(&masked),
+            vec![
+                ("DbPassword".to_string(), "****".to_string()),
+                ("VpcCidr".to_string(), "10.0.0.0/16".to_string()),
+            ]
+        );
+
+        let plain = status(Some(deployed()), &[]);
+        assert_eq!(parameters(&plain)[0].1, "hunter2");
+    }
 }
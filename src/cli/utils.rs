@@ -1,17 +1,213 @@
-use aws_sdk_cloudformation::types::{ResourceStatus, StackEvent};
+use aws_sdk_cloudformation::types::ResourceStatus;
 use colored::Colorize;
-use indicatif::{ProgressBar, ProgressStyle};
 // use isatty::stdout_isatty;
 use aws_config::{self, BehaviorVersion};
 use aws_sdk_cloudformation::Client;
 use chrono::{TimeZone, Utc};
-use dialoguer::{theme::ColorfulTheme, MultiSelect, Select};
+use dialoguer::{theme::ColorfulTheme, Confirm, Input, MultiSelect, Password, Select};
 use regex::Regex;
-use std::collections::HashMap;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, IsTerminal};
 use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::OnceLock;
 use std::thread;
-use tokio::time::{sleep, Duration};
+use tokio::sync::broadcast;
+use tokio::time::{sleep, sleep_until, Duration, Instant};
+
+use crate::output::human;
+use crate::progress;
+
+// set when several stacks are processed at the same time. Prompts are then
+// skipped and the output of hooks is prefixed with the stack name
+static CONCURRENT: AtomicBool = AtomicBool::new(false);
+
+pub fn set_concurrent(concurrent: bool) {
+    CONCURRENT.store(concurrent, Ordering::SeqCst);
+}
+
+pub fn is_concurrent() -> bool {
+    CONCURRENT.load(Ordering::SeqCst)
+}
+
+// how long a stack operation is watched before asking what to do, set
+// with the global --timeout flag. Waits have no timeout by default
+static TIMEOUT: OnceLock<Duration> = OnceLock::new();
+
+// number of stack operations being watched. Ctrl-C stops kloi
+// right away unless an operation is being watched
+static WATCHING: AtomicUsize = AtomicUsize::new(0);
+static INTERRUPTS: OnceLock<broadcast::Sender<()>> = OnceLock::new();
+
+pub fn set_timeout(timeout: Duration) {
+    let _ = TIMEOUT.set(timeout);
+}
+
+// handle_interrupts listens for ctrl-c. While a stack operation is watched
+// the watcher is woken up to offer cancelling the operation, otherwise
+// kloi exits
+pub fn handle_interrupts() {
+    let tx = INTERRUPTS.get_or_init(|| broadcast::channel(4).0).clone();
+    tokio::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if WATCHING.load(Ordering::SeqCst) == 0 || tx.send(()).is_err() {
+                eprintln!();
+                std::process::exit(130);
+            }
+        }
+    });
+}
+
+// Wake is why a watcher stopped pausing between two polls
+#[derive(Clone, Copy, PartialEq)]
+enum Wake {
+    Poll,
+    TimedOut,
+    Interrupted,
+}
+
+// Choice is what to do with an operation that timed out or was interrupted
+#[derive(Clone, Copy, PartialEq)]
+enum Choice {
+    Cancel,
+    Stop,
+    Keep,
+}
+
+// Watcher paces the polling of a stack operation, waking up early
+// when the operation times out or ctrl-c is pressed
+struct Watcher {
+    deadline: Option<Instant>,
+    interrupts: Option<broadcast::Receiver<()>>,
+    // set once the update was cancelled
+    cancelled: bool,
+    // token of the cancel request, whose events are followed too
+    cancel_token: Option<String>,
+}
+
+impl Watcher {
+    fn new() -> Self {
+        WATCHING.fetch_add(1, Ordering::SeqCst);
+        Watcher {
+            deadline: TIMEOUT.get().map(|t| Instant::now() + *t),
+            interrupts: INTERRUPTS.get().map(|tx| tx.subscribe()),
+            cancelled: false,
+            cancel_token: None,
+        }
+    }
+
+    async fn pause(&mut self) -> Wake {
+        let deadline = self.deadline;
+        let interrupts = &mut self.interrupts;
+        tokio::select! {
+            _ = sleep(Duration::from_secs(2)) => Wake::Poll,
+            _ = async {
+                match deadline {
+                    Some(d) => sleep_until(d).await,
+                    None => std::future::pending().await,
+                }
+            } => Wake::TimedOut,
+            _ = async {
+                match interrupts {
+                    Some(rx) => { let _ = rx.recv().await; }
+                    None => std::future::pending().await,
+                }
+            } => Wake::Interrupted,
+        }
+    }
+
+    // choose asks what to do with an operation that timed out or was
+    // interrupted. Updates in progress can be cancelled. Runs that can't
+    // prompt stop watching
+    fn choose(&self, stack_name: &str, wake: Wake, status: &str) -> Choice {
+        let interactive = std::io::stdin().is_terminal()
+            && std::io::stdout().is_terminal()
+            && !CONCURRENT.load(Ordering::SeqCst);
+        if !interactive {
+            return Choice::Stop;
+        }
+
+        let mut choices = Vec::new();
+        if status == "UPDATE_IN_PROGRESS" && !self.cancelled {
+            choices.push((Choice::Cancel, "cancel the update and roll it back"));
+        }
+        choices.push((Choice::Stop, "stop watching, the operation continues"));
+        choices.push((Choice::Keep, "keep watching"));
+
+        let reason = match wake {
+            Wake::TimedOut => "timed out",
+            _ => "interrupted",
+        };
+        let selected = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("[{}] {}, what should happen?", stack_name, reason))
+            .items(&choices.iter().map(|(_, c)| *c).collect::<Vec<&str>>())
+            .default(0)
+            .interact()
+            .unwrap_or(choices.len() - 1);
+
+        choices[selected].0
+    }
+
+    // act carries out a choice. An error is returned if the operation
+    // is no longer watched
+    async fn act(
+        &mut self,
+        client: &Client,
+        stack_name: &str,
+        wake: Wake,
+        choice: Choice,
+    ) -> Result<(), String> {
+        match choice {
+            Choice::Cancel => {
+                let token = request_token();
+                client
+                    .cancel_update_stack()
+                    .stack_name(stack_name)
+                    .client_request_token(&token)
+                    .send()
+                    .await
+                    .map_err(|e| {
+                        format!(
+                            "[{}] error cancelling update: {}",
+                            stack_name,
+                            e.into_service_error()
+                        )
+                    })?;
+                log::warn!("[{}] {}", stack_name.cyan(), "update cancelled, rolling back".yellow());
+                // follow the rollback until it is done
+                self.cancelled = true;
+                self.cancel_token = Some(token);
+                self.deadline = None;
+                Ok(())
+            }
+            Choice::Keep => {
+                self.deadline = TIMEOUT.get().map(|t| Instant::now() + *t);
+                Ok(())
+            }
+            Choice::Stop => Err(format!(
+                "[{}] stopped watching the stack after it {}, the operation continues in the background",
+                stack_name,
+                match wake {
+                    Wake::TimedOut => "timed out",
+                    _ => "was interrupted",
+                }
+            )),
+        }
+    }
+
+    // finish returns an error if the watched update was cancelled
+    fn finish(&self, stack_name: &str) -> Result<(), String> {
+        if self.cancelled {
+            return Err(format!("[{}] update cancelled and rolled back", stack_name));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        WATCHING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
 
 // enum for wait events
 #[derive(Clone)]
@@ -19,6 +215,9 @@ pub enum WaitEvent {
     Create,
     Update,
     Delete,
+    Import,
+    // continuing the rollback of a failed update
+    Rollback,
 }
 
 impl WaitEvent {
@@ -27,10 +226,32 @@ impl WaitEvent {
             WaitEvent::Create => "create".to_string(),
             WaitEvent::Update => "update".to_string(),
             WaitEvent::Delete => "delete".to_string(),
+            WaitEvent::Import => "import".to_string(),
+            WaitEvent::Rollback => "rollback".to_string(),
+        }
+    }
+
+    // start_status is the status of the stack event starting the operation
+    fn start_status(&self) -> String {
+        match self {
+            WaitEvent::Rollback => "UPDATE_ROLLBACK_IN_PROGRESS".to_string(),
+            _ => format!("{}_IN_PROGRESS", self.to_string().to_uppercase()),
         }
     }
 }
 
+// request_token returns a new client request token. The events of the
+// operation a request starts carry its token, which tells them apart
+// from the events of earlier operations
+pub fn request_token() -> String {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    format!(
+        "kloi-{}-{}",
+        Utc::now().timestamp_millis(),
+        COUNT.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
 fn format_status(status: Option<&ResourceStatus>) -> String {
     match status.clone().unwrap() {
         ResourceStatus::CreateComplete => "create complete".green(),
@@ -70,134 +291,93 @@ fn format_status(status: Option<&ResourceStatus>) -> String {
 // use colored::*;
 // use futures::StreamExt;
 
+// stackprogress follows a stack operation until it is complete or failed.
+// token is the client request token of the request starting the operation.
+// An error is returned if the operation failed or was rolled back
 pub async fn stackprogress(
     client: &Client,
     stack_name: &str,
     custom_respirces: Option<Vec<String>>,
     region: String,
     wait_event: WaitEvent,
+    token: Option<&str>,
 ) -> Result<(), String> {
-    if std::env::var("KLOI_LOG").unwrap_or("".to_string()) == "debug" {
-        return wait_for_stack(client, stack_name, wait_event).await;
+    let status = watch_stack(
+        client,
+        stack_name,
+        custom_respirces,
+        region,
+        wait_event.clone(),
+        token,
+    )
+    .await?;
+
+    match status {
+        Some(s) if is_failed(&wait_event, &s) => Err(format!(
+            "[{}] {} failed, stack is in {} state",
+            stack_name,
+            wait_event.to_string(),
+            s
+        )),
+        _ => Ok(()),
     }
+}
 
-    // let (tx, mut rx) = mpsc::channel(100);
-
-    let pb = ProgressBar::new(0);
-    let spinner_style =
-        ProgressStyle::with_template("{prefix:.bold.dim} {spinner:.green}{wide_msg}\n")
-            .unwrap()
-            .tick_strings(&[
-                "[ ●    ]",
-                "[  ●   ]",
-                "[   ●  ]",
-                "[    ● ]",
-                "[     ●]",
-                "[    ● ]",
-                "[   ●  ]",
-                "[  ●   ]",
-                "[ ●    ]",
-                "[●     ]",
-                "",
-            ]);
-    pb.set_style(spinner_style.clone());
-    pb.enable_steady_tick(Duration::from_millis(100));
-    pb.set_prefix(format!("[{}] [{}]", "info".green(), stack_name.cyan()));
-
-    let break_re = Regex::new(r"complete|failed").unwrap();
-    let mut status = String::new();
-    // let mut events: Vec<String> = Vec::new();
-    let mut events = HashMap::<String, Option<String>>::new();
-    // let previous_status = String::new();
-
-    while !break_re.is_match(status.to_lowercase().as_str()) {
-        let status_res = client.describe_stacks().stack_name(stack_name).send().await;
-
-        match status_res {
-            Ok(r) => {
-                r.stacks().iter().for_each(|s| {
-                    status = s
-                        .stack_status()
-                        .unwrap() // TODO: handle unwrap
-                        .to_string();
-
-                    let stk = ResourceStatus::from(status.as_str());
-
-                    pb.set_prefix(format!(
-                        "[{}] [{}] {}",
-                        "info".green(),
-                        stack_name.cyan(),
-                        format_status(Some(&stk))
-                    ));
-                });
-            }
-            Err(e) => {
-                let err = format!(
-                    "error describing stack for wait: {:?}",
-                    e.into_service_error().meta().message()
-                );
-
-                if !err.contains("does not exist") {
-                    Err(err.clone())?;
-                }
-
-                // assume deleted if stack does not exist
-                if let WaitEvent::Delete = wait_event {
-                    pb.set_prefix(format!("[{}] [{}]", "info".green(), stack_name.cyan()));
-                    pb.finish_with_message(format!("{}", "does not exist".yellow()));
-                    return Ok(());
-                }
-
-                Err(err)?;
-            }
-        }
-
-        let stack_events_res = client
-            .describe_stack_events()
-            .stack_name(stack_name)
-            .send()
-            .await;
-
-        let failed_events: Vec<&StackEvent> = match stack_events_res.as_ref() {
-            Ok(r) => r
-                .stack_events()
-                .iter()
-                .filter(|e| {
-                    return format_status(e.resource_status()).contains("failed");
-                })
-                .collect(),
-            Err(_) => {
-                pb.finish_with_message(format!("[{}] error", stack_name.cyan()));
-                return Err(format!("[{}] error", stack_name.cyan()));
-            }
-        };
-
-        for e in failed_events.iter() {
-            let event_status = format_status(e.resource_status());
-            let resource = e.resource_type().unwrap();
-            let reason = e.resource_status_reason().unwrap_or("executing");
-
-            let msg = format!(
-                "\n---\n{}\n{}\nstatus: {}\nreason: {}",
-                e.logical_resource_id().unwrap(),
-                resource.truecolor(45, 51, 59),
-                event_status.truecolor(45, 51, 59),
-                reason.red()
-            );
-            if events.contains_key(&msg) {
-                continue;
-            }
+// is_failed checks if the final status of a stack means its operation failed
+fn is_failed(wait_event: &WaitEvent, status: &str) -> bool {
+    match wait_event {
+        // rolling back is what was asked for
+        WaitEvent::Rollback => status != "UPDATE_ROLLBACK_COMPLETE",
+        _ => status.contains("FAILED") || status.contains("ROLLBACK"),
+    }
+}
 
-            events.insert(msg, None);
-        }
+// watch_stack follows a stack operation until it is complete or failed,
+// without judging how it ended. The final status of the stack is
+// returned, or None once the stack is deleted
+pub async fn watch_stack(
+    client: &Client,
+    stack_name: &str,
+    custom_respirces: Option<Vec<String>>,
+    region: String,
+    wait_event: WaitEvent,
+    token: Option<&str>,
+) -> Result<Option<String>, String> {
+    let mut operation = progress::Operation::new(stack_name, &wait_event.start_status(), token);
+    if std::env::var("KLOI_LOG").unwrap_or("".to_string()) == "debug" {
+        return wait_for_stack(client, stack_name, wait_event, &mut operation).await;
+    }
 
-        let e: Vec<String> = events.keys().map(|v| v.to_string()).collect();
-        pb.set_message(e.join("\n"));
+    let mut line = progress::StackLine::new(stack_name);
+    let mut watcher = Watcher::new();
+    let followed = follow_stack(
+        client,
+        stack_name,
+        &wait_event,
+        &mut line,
+        &mut operation,
+        &mut watcher,
+    )
+    .await;
+
+    let (done, total) = operation.progress();
+    let (status, failed) = match &followed {
+        Ok(Some(s)) => (
+            format_status(Some(&ResourceStatus::from(s.as_str()))),
+            is_failed(&wait_event, s),
+        ),
+        Ok(None) => ("delete complete".green().to_string(), false),
+        Err(_) => ("error".red().to_string(), true),
+    };
+    let resources = operation.resources();
+    let failures: Vec<&progress::Resource> = resources.iter().filter(|r| r.is_failed()).collect();
+    line.finish(&status, done, total, failed, &resource_rows(&failures));
 
-        sleep(Duration::from_secs(2)).await;
-    }
+    let status = followed?;
+    watcher.finish(stack_name)?;
 
     // get custom resources
+    let mut logs = Vec::new();
     if let Some(crs) = custom_respirces {
         for cr in crs.iter() {
             let physical_id = client
@@ -221,69 +401,145 @@ pub async fn stackprogress(
                 .unwrap()
                 .to_string(); // TODO: handle unwrap
 
-            let logs = get_cloudwatch_logs(physical_id, region.clone()).await?;
-            // pb.finish_with_message(format!("[{}] {}", cr.cyan(), logs));
-            events.insert(
-                format!("\n---\n[{}]\n{}---\n", cr.bold(), logs)
+            let cr_logs = get_cloudwatch_logs(physical_id, region.clone()).await?;
+            logs.push(
+                format!("---\n[{}]\n{}---", cr.bold(), cr_logs)
                     .truecolor(96, 96, 96)
                     .to_string(),
-                None,
             );
         }
     }
 
-    let e: Vec<String> = events.keys().map(|v| v.to_string()).collect();
-    // pb.finish_with_message(format!("{}", e.join("\n")));
-    pb.finish();
-    println!("{}", e.join("\n"));
+    if !logs.is_empty() {
+        progress::suspend(|| human!("{}", logs.join("\n")));
+    }
 
-    Ok(())
+    Ok(status)
+}
+
+// resource_rows renders resources as a table, with how long each resource
+// took, the statuses it went through and why it failed. The statuses are
+// colored already, they are the last column so they are never padded
+fn resource_rows(resources: &[&progress::Resource]) -> Vec<String> {
+    let rows: Vec<[String; 4]> = resources
+        .iter()
+        .map(|r| {
+            let statuses: Vec<String> = r.statuses.iter().map(|s| format_status(Some(s))).collect();
+            [
+                r.logical_id.clone(),
+                r.resource_type.clone(),
+                crate::output::format_duration(r.duration()),
+                statuses.join(&" → ".truecolor(96, 96, 96).to_string()),
+            ]
+        })
+        .collect();
+
+    let lines = crate::output::table(&[], &rows, |_, i, cell| match i {
+        1 | 2 => cell.truecolor(96, 96, 96).to_string(),
+        _ => cell,
+    });
+
+    lines
+        .into_iter()
+        .zip(resources.iter())
+        .map(|(line, r)| {
+            let mut row = format!("  {}", line);
+            if let Some(reason) = r.reason.as_ref().filter(|_| r.is_failed()) {
+                row.push_str(&format!("\n    {}", reason.red()));
+            }
+            row
+        })
+        .collect()
+}
+
+// follow_stack polls a stack until its operation is complete or failed,
+// keeping the line of the stack up to date
+async fn follow_stack(
+    client: &Client,
+    stack_name: &str,
+    wait_event: &WaitEvent,
+    line: &mut progress::StackLine,
+    operation: &mut progress::Operation,
+    watcher: &mut Watcher,
+) -> Result<Option<String>, String> {
+    let break_re = Regex::new(r"complete|failed").unwrap();
+
+    loop {
+        let Some(deployed) = describe_stack(client, stack_name).await? else {
+            // assume deleted if stack does not exist
+            if let WaitEvent::Delete = wait_event {
+                return Ok(None);
+            }
+            return Err(format!("[{}] stack does not exist", stack_name));
+        };
+        let status = deployed
+            .stack_status()
+            .map(|s| s.as_str())
+            .unwrap_or_default()
+            .to_string();
+
+        operation.poll(client).await?;
+        let (done, total) = operation.progress();
+        line.update(
+            &format_status(Some(&ResourceStatus::from(status.as_str()))),
+            done,
+            total,
+        );
+
+        let resources = operation.resources();
+        line.set_rows(&resource_rows(&resources.iter().collect::<Vec<_>>()));
+
+        if break_re.is_match(status.to_lowercase().as_str()) {
+            return Ok(Some(status));
+        }
+
+        let wake = watcher.pause().await;
+        if wake != Wake::Poll {
+            let choice = progress::suspend(|| watcher.choose(stack_name, wake, &status));
+            watcher.act(client, stack_name, wake, choice).await?;
+            if let Some(token) = watcher.cancel_token.take() {
+                operation.accept(&token);
+            }
+        }
+    }
 }
 
-// wait_for_stack_completion waits for a stack to reach a failed or complete state
+// wait_for_stack_completion waits for a stack to reach a failed or complete state,
+// printing the events of the current operation as lines
 pub async fn wait_for_stack(
     client: &aws_sdk_cloudformation::Client,
     stack_name: &str,
     wait_event: WaitEvent,
-) -> Result<(), String> {
-    let mut do_break = false;
+    operation: &mut progress::Operation,
+) -> Result<Option<String>, String> {
     let break_re = Regex::new(r"complete|failed").unwrap();
 
-    // hash of string, none type
-    let mut seen = std::collections::HashMap::<String, Option<String>>::new();
-    let mut current_status = String::new();
     let mut previous_status = String::new();
-    let stack_status_res = client.describe_stacks().stack_name(stack_name).send().await;
-    match stack_status_res {
-        Ok(r) => {
-            r.stacks().iter().for_each(|s| {
-                current_status = s
-                    .stack_status()
-                    .unwrap() // TODO: handle unwrap
-                    .to_string();
-            });
-        }
-        Err(e) => {
-            Err(format!(
-                "error describing stack for wait: {}",
-                e.into_service_error()
-            ))?;
-        }
-    }
+    let mut watcher = Watcher::new();
 
-    'outer: loop {
-        let stack_events_res = client
-            .describe_stack_events()
-            .stack_name(stack_name)
-            .send()
-            .await;
+    loop {
+        let Some(deployed) = describe_stack(client, stack_name).await? else {
+            // assume deleted if stack does not exist
+            if let WaitEvent::Delete = wait_event {
+                log::info!(
+                    "[{}] {} - {}",
+                    stack_name.cyan(),
+                    "delete complete".green(),
+                    "stack does not exist"
+                );
+                watcher.finish(stack_name)?;
+                return Ok(None);
+            }
+            return Err(format!("[{}] stack does not exist", stack_name));
+        };
+        let current_status = deployed
+            .stack_status()
+            .map(|s| s.as_str())
+            .unwrap_or_default()
+            .to_string();
 
         if current_status != previous_status {
             let s = ResourceStatus::from(current_status.as_str());
-            // log::info!("[{}] {}: {}",
-            //     stack_name.cyan(),
-            //     "status".truecolor(96, 96, 96),
-            //     format_status(Some(&s)));
             log::info!(
                 "{}",
                 format!("[{}] {}", stack_name, &s).truecolor(96, 96, 96)
@@ -292,93 +548,44 @@ pub async fn wait_for_stack(
             previous_status = current_status.clone();
         }
 
-        match stack_events_res {
-            Ok(r) => {
-                r.stack_events().iter().for_each(|e| {
-                    let status = format_status(e.resource_status());
-                    let reason = match e.resource_status_reason() {
-                        Some(r) => r,
-                        None => "executing",
-                    };
-
-                    let msg = format!(
-                        "[{0: <1}] {1: <35} {2: <30} {3: <10}",
-                        // "[{:<20}] {:<20} {:<35} {}",
-                        e.stack_name().unwrap().cyan(),
-                        status,
-                        e.resource_type().unwrap().bright_purple(),
-                        // e.stack_id().unwrap(),
-                        reason
-                    );
-
-                    if !seen.contains_key(&msg) && status.contains(wait_event.to_string().as_str())
-                    {
-                        log::info!("{}", msg);
-                        seen.insert(msg, None);
-                    }
-                });
-            }
-            Err(e) => {
-                let err = format!(
-                    "error describing stack for wait: {:?}",
-                    e.into_service_error().to_string()
-                );
-
-                if !err.contains("does not exist") {
-                    Err(err.clone())?;
-                }
-
-                // assume deleted if stack does not exist
-                if let WaitEvent::Delete = wait_event {
-                    log::info!(
-                        "[{}] {} - {}",
-                        stack_name.cyan(),
-                        "delete complete".green(),
-                        "stack does not exist"
-                    );
-                    return Ok(());
-                }
-
-                Err(err)?;
+        // events of earlier operations on the stack are left out
+        for e in operation.poll(client).await? {
+            if operation.is_stack(&e) {
+                continue;
             }
-        }
-
-        // check stack statu
-        let stack_res = client.describe_stacks().stack_name(stack_name).send().await;
 
-        // check stack status and break if complete or failed
-        match stack_res {
-            Ok(r) => {
-                r.stacks().iter().for_each(|s| {
-                    current_status = s.stack_status().unwrap().to_string();
-                    if break_re.is_match(&current_status.to_lowercase().as_str()) {
-                        do_break = true;
-                    }
-                });
-            }
-            Err(e) => {
-                Err(format!(
-                    "error describing stack for wait: {}",
-                    e.into_service_error()
-                ))?;
-            }
+            let took = operation
+                .took(&e)
+                .map(crate::output::format_duration)
+                .unwrap_or_default();
+            log::info!(
+                "[{0: <1}] {1: <30} {2: <35} {3: <30} {4: <8} {5}",
+                stack_name.cyan(),
+                e.logical_resource_id().unwrap_or_default(),
+                format_status(e.resource_status()),
+                e.resource_type().unwrap_or_default().bright_purple(),
+                took.truecolor(96, 96, 96),
+                e.resource_status_reason().unwrap_or_default()
+            );
         }
 
         // break if stack is complete or failed
-        if do_break {
-            let s = ResourceStatus::from(current_status.as_str());
-            log::info!(
-                "{}",
-                format!("[{}] {}", stack_name, &s).truecolor(96, 96, 96)
-            );
-            break 'outer;
+        if break_re.is_match(current_status.to_lowercase().as_str()) {
+            break;
         }
 
-        // sleep for 2 seconds
-        sleep(Duration::from_secs(2)).await;
+        let wake = watcher.pause().await;
+        if wake != Wake::Poll {
+            let choice = watcher.choose(stack_name, wake, &current_status);
+            watcher.act(client, stack_name, wake, choice).await?;
+            if let Some(token) = watcher.cancel_token.take() {
+                operation.accept(&token);
+            }
+        }
     }
 
-    Ok(())
+    watcher.finish(stack_name)?;
+    Ok(Some(previous_status))
 }
 
 // stack_exists checks if a stack exists
@@ -405,6 +612,107 @@ pub async fn stack_exists(
     }
 }
 
+// describe_stack returns a deployed stack, or None if it does not exist
+pub async fn describe_stack(
+    client: &aws_sdk_cloudformation::Client,
+    stack_name: &str,
+) -> Result<Option<aws_sdk_cloudformation::types::Stack>, String> {
+    match client.describe_stacks().stack_name(stack_name).send().await {
+        Ok(r) => Ok(r.stacks().first().cloned()),
+        Err(e) => {
+            let err = e.into_service_error();
+            if err.meta().message().unwrap_or_default().contains("does not exist") {
+                return Ok(None);
+            }
+            Err(format!("[{}] error describing stack: {}", stack_name, err))
+        }
+    }
+}
+
+// is_busy checks if an operation is in progress on a stack. Stacks in
+// review only have change sets that were not executed, they are idle
+pub fn is_busy(s: &aws_sdk_cloudformation::types::Stack) -> bool {
+    s.stack_status().is_some_and(|status| {
+        status.as_str().ends_with("_IN_PROGRESS")
+            && *status != aws_sdk_cloudformation::types::StackStatus::ReviewInProgress
+    })
+}
+
+// wait_for_idle follows the operations in progress on a stack until the
+// stack is stable. The stack is returned if it still exists
+pub async fn wait_for_idle(
+    client: &aws_sdk_cloudformation::Client,
+    stack_name: &str,
+    region: String,
+) -> Result<Option<aws_sdk_cloudformation::types::Stack>, String> {
+    loop {
+        let Some(deployed) = describe_stack(client, stack_name).await? else {
+            return Ok(None);
+        };
+        if !is_busy(&deployed) {
+            return Ok(Some(deployed));
+        }
+
+        let status = deployed.stack_status().map(|s| s.as_str()).unwrap_or("-");
+        log::info!(
+            "[{}] {} {} {}",
+            stack_name.cyan(),
+            "waiting for".yellow(),
+            status.to_lowercase().replace('_', " ").yellow(),
+            "to finish".yellow()
+        );
+
+        // statuses such as UPDATE_COMPLETE_CLEANUP_IN_PROGRESS end the
+        // wait early, the stack is checked again until it is idle
+        let event = busy_event(status);
+        watch_stack(client, stack_name, None, region.clone(), event, None).await?;
+    }
+}
+
+// busy_event returns the operation a stack status in progress belongs to
+fn busy_event(status: &str) -> WaitEvent {
+    match status {
+        st if st.starts_with("CREATE") || st.starts_with("ROLLBACK") => WaitEvent::Create,
+        st if st.starts_with("DELETE") => WaitEvent::Delete,
+        st if st.starts_with("IMPORT") => WaitEvent::Import,
+        _ => WaitEvent::Update,
+    }
+}
+
+// list_stacks returns every stack deployed in a region
+pub async fn list_stacks(
+    region: &str,
+) -> Result<Vec<aws_sdk_cloudformation::types::Stack>, String> {
+    let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+        .region(aws_types::region::Region::new(region.to_string()))
+        .load()
+        .await;
+    let client = aws_sdk_cloudformation::Client::new(&sdk_config);
+
+    client
+        .describe_stacks()
+        .into_paginator()
+        .items()
+        .send()
+        .collect::<Result<Vec<_>, _>>()
+        .await
+        .map_err(|e| {
+            format!(
+                "[{}] error occurred while listing stacks: {}",
+                region,
+                e.into_service_error()
+            )
+        })
+}
+
+// stack_tag returns the value of a tag on a deployed stack
+pub fn stack_tag<'a>(s: &'a aws_sdk_cloudformation::types::Stack, key: &str) -> Option<&'a str> {
+    s.tags()
+        .iter()
+        .find(|t| t.key() == Some(key))
+        .and_then(|t| t.value())
+}
+
 // stack_exec used to execute subprocess commands
 // for stack events
 pub fn stack_exec(
@@ -440,9 +748,16 @@ pub fn stack_exec(
                 .lines()
                 .filter_map(|line| line.ok())
                 .for_each(|line| {
-                    println!("{}", line.truecolor(96, 96, 96));
+                    // keep the output of concurrent hooks apart
+                    if CONCURRENT.load(Ordering::SeqCst) {
+                        progress::suspend(|| {
+                            human!("[{}] {}", stack_name.cyan(), line.truecolor(96, 96, 96))
+                        });
+                    } else {
+                        progress::suspend(|| human!("{}", line.truecolor(96, 96, 96)));
+                    }
                 });
-            println!("---")
+            progress::suspend(|| human!("---"))
         });
 
         // Wait for the reader thread to finish.å
@@ -476,6 +791,13 @@ pub fn stack_exec(
     Ok(())
 }
 
+// no_updates checks if an error message is cloudformation
+// reporting that a request has nothing to change
+pub fn no_updates(message: &str) -> bool {
+    message.contains("No updates are to be performed")
+        || message.contains("didn't contain changes")
+}
+
 // get_cloudwatch_logs
 async fn get_cloudwatch_logs(lambda_id: String, region: String) -> Result<String, String> {
     let loggroup = format!("/aws/lambda/{}", lambda_id);
@@ -622,9 +944,7 @@ macro_rules! exec_jobs {
                     continue;
                 }
 
-                if let Err(e) = utils::stack_exec($name, job.name.clone(), job.run.clone()) {
-                    return Err(e);
-                }
+                utils::stack_exec($name, job.name.clone(), job.run.clone())?;
             }
         }
     };
@@ -672,3 +992,97 @@ pub fn singleselect(opts: Vec<String>, prompt: &str) -> String {
 
     opts[selections].clone()
 }
+
+pub fn confirm(prompt: &str) -> bool {
+    Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .default(false)
+        .interact()
+        .unwrap_or(false)
+}
+
+// approve asks to confirm an action unless --yes was passed. Runs that
+// can't prompt fail instead of silently declining
+pub fn approve(prompt: &str, yes: bool) -> Result<bool, String> {
+    if yes {
+        return Ok(true);
+    }
+
+    let interactive = std::io::stdin().is_terminal()
+        && std::io::stdout().is_terminal()
+        && !is_concurrent();
+    if !interactive {
+        return Err(format!(
+            "{} needs confirmation, pass --yes to run without a prompt",
+            prompt.trim_end_matches('?')
+        ));
+    }
+
+    Ok(progress::suspend(|| confirm(prompt)))
+}
+
+// prompt_parameter asks for the value of a template parameter, using
+// a select list for allowed values and a hidden input for NoEcho parameters
+pub fn prompt_parameter(
+    name: &str,
+    param: &crate::template::TemplateParameter,
+) -> Result<String, String> {
+    let prompt = match &param.description {
+        Some(d) => format!("{} ({})", name, d),
+        None => name.to_string(),
+    };
+    let theme = ColorfulTheme::default();
+    let err = |e: dialoguer::Error| format!("failed to read parameter [{}]: {}", name, e);
+
+    if let Some(allowed) = &param.allowed_values {
+        let i = Select::with_theme(&theme)
+            .with_prompt(prompt)
+            .items(&allowed[..])
+            .interact()
+            .map_err(err)?;
+        return Ok(allowed[i].clone());
+    }
+
+    if param.no_echo {
+        return Password::with_theme(&theme)
+            .with_prompt(prompt)
+            .interact()
+            .map_err(err);
+    }
+
+    Input::<String>::with_theme(&theme)
+        .with_prompt(prompt)
+        .interact_text()
+        .map_err(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_busy_event() {
+        let cases = [
+            ("CREATE_IN_PROGRESS", "create"),
+            ("ROLLBACK_IN_PROGRESS", "create"),
+            ("DELETE_IN_PROGRESS", "delete"),
+            ("IMPORT_ROLLBACK_IN_PROGRESS", "import"),
+            ("UPDATE_COMPLETE_CLEANUP_IN_PROGRESS", "update"),
+            ("UPDATE_ROLLBACK_IN_PROGRESS", "update"),
+        ];
+        for (status, event) in cases {
+            assert_eq!(busy_event(status).to_string(), event, "{}", status);
+        }
+    }
+
+    #[test]
+    fn test_no_updates() {
+        assert!(no_updates("No updates are to be performed."));
+        assert!(no_updates(
+            "The submitted information didn't contain changes. Submit different information to create a change set."
+        ));
+        assert!(!no_updates(
+            "Stack:arn:aws:cloudformation:eu-west-1:123456789012:stack/api is in UPDATE_IN_PROGRESS state and can not be updated."
+        ));
+    }
+}
@@ -0,0 +1,152 @@
+use aws_config::{self, BehaviorVersion};
+use aws_sdk_cloudformation::types::{Stack, StackStatus};
+use aws_types::region::Region;
+use clap::ArgMatches;
+use clap::{arg, Command};
+use colored::Colorize;
+use log;
+use std::env;
+use std::time::Instant;
+
+use crate::config;
+use crate::output::{self, human};
+use crate::utils;
+
+const ABOUT: &str = r#"wait for a stack to reach a stable state,
+exits with a code depending on the final status of the stack:
+  0  the last operation succeeded
+  2  the last operation was rolled back
+  3  the stack is in a failed state
+  4  the stack does not exist
+"#;
+
+pub fn command() -> Command {
+    Command::new("wait")
+        .about(ABOUT.truecolor(125, 174, 189).to_string())
+        .arg(arg!(<stack> "name of the stack to wait for"))
+        .arg(arg!(-r --region <REGION> "region of the stack, defaults to the region in the config"))
+        .arg(arg!(-c --config <FILE> "path to config file"))
+}
+
+pub async fn handle(matches: &ArgMatches) -> Result<(), String> {
+    // note: unwrap is fine here, the argument is required
+    let name = matches.get_one::<String>("stack").unwrap();
+
+    // the config is only used to look up the region of the stack,
+    // so stacks that are not managed by kloi can be waited for too
+    let config_path = env::var("KLOI_CONFIG")
+        .ok()
+        .or(matches.get_one::<String>("config").cloned());
+    let region = match (matches.get_one::<String>("region"), config_path) {
+        (Some(r), _) => r.clone(),
+        (None, Some(path)) => config::load_config_from_file(path)?
+            .stacks
+            .iter()
+            .find(|s| &s.name == name)
+            .and_then(|s| s.region.clone())
+            .unwrap_or("eu-west-1".to_string()),
+        (None, None) => "eu-west-1".to_string(),
+    };
+
+    let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+        .region(Region::new(region.clone()))
+        .load()
+        .await;
+    let client = aws_sdk_cloudformation::Client::new(&sdk_config);
+
+    let started = Instant::now();
+    let deployed = utils::wait_for_idle(&client, name, region.clone()).await?;
+    let code = exit_code(deployed.as_ref());
+
+    let status = deployed
+        .as_ref()
+        .and_then(|d| d.stack_status())
+        .map(|s| s.as_str().to_lowercase())
+        .unwrap_or("does not exist".to_string());
+    let colored_status = match code {
+        0 => status.green(),
+        2 | 4 => status.yellow(),
+        _ => status.red(),
+    };
+    human!("[{}] {}", name.cyan(), colored_status);
+    if let Some(reason) = deployed.as_ref().and_then(|d| d.stack_status_reason()) {
+        human!("  {}", reason.truecolor(96, 96, 96));
+    }
+
+    let mut report = output::Report::new("wait");
+    report.stacks.push(output::StackResult {
+        name: name.clone(),
+        region,
+        status: Some(status.clone()),
+        status_reason: deployed
+            .as_ref()
+            .and_then(|d| d.stack_status_reason())
+            .map(|r| r.to_string()),
+        duration_ms: Some(started.elapsed().as_millis() as u64),
+        ..Default::default()
+    });
+    let result = match code {
+        0 => Ok(()),
+        _ => Err(format!("[{}] stack is {}", name, status)),
+    };
+    report.emit(&result)?;
+
+    if code != 0 {
+        log::debug!("[{}] exiting with code {}", name, code);
+        std::process::exit(code);
+    }
+
+    Ok(())
+}
+
+// exit_code maps the final status of a stack to the exit code of wait
+pub fn exit_code(deployed: Option<&Stack>) -> i32 {
+    let Some(status) = deployed.and_then(|d| d.stack_status()) else {
+        return 4;
+    };
+
+    match status {
+        StackStatus::CreateComplete | StackStatus::UpdateComplete | StackStatus::ImportComplete => {
+            0
+        }
+        StackStatus::RollbackComplete
+        | StackStatus::UpdateRollbackComplete
+        | StackStatus::ImportRollbackComplete => 2,
+        // change sets that were never executed leave an empty stack behind
+        StackStatus::ReviewInProgress | StackStatus::DeleteComplete => 4,
+        _ => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_exit_code() {
+        let stack = |status: StackStatus| {
+            Stack::builder()
+                .stack_name("test")
+                .stack_status(status)
+                .build()
+        };
+
+        assert_eq!(exit_code(None), 4);
+        for (status, code) in [
+            (StackStatus::CreateComplete, 0),
+            (StackStatus::UpdateComplete, 0),
+            (StackStatus::UpdateRollbackComplete, 2),
+            (StackStatus::RollbackComplete, 2),
+            (StackStatus::UpdateRollbackFailed, 3),
+            (StackStatus::DeleteFailed, 3),
+            (StackStatus::ReviewInProgress, 4),
+        ] {
+            assert_eq!(
+                exit_code(Some(&stack(status.clone()))),
+                code,
+                "{:?}",
+                status
+            );
+        }
+    }
+}
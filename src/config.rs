@@ -1,6 +1,8 @@
 // use crate::cli::sources::*;
 use crate::config;
+use crate::parameters;
 use crate::stacks;
+use crate::values;
 
 use starlark::collections::SmallMap;
 use starlark::environment::{GlobalsBuilder, Module};
@@ -8,7 +10,7 @@ use starlark::eval::Evaluator;
 use starlark::syntax::{AstModule, Dialect};
 use starlark::values::ValueLike;
 
-use starlark::values::{list, none::NoneType, ProvidesStaticType, Value};
+use starlark::values::{list, none::NoneType, ProvidesStaticType, StarlarkValue, Value};
 use starlark_derive::starlark_module;
 use std::cell::RefCell;
 use std::collections::HashMap;
@@ -50,19 +52,40 @@ impl Source for String {
 
 pub struct Config {
     pub stacks: Vec<stacks::Stack>,
+    // identifies the stacks deployed from this config, see stacks.project
+    pub project: Option<String>,
+}
+
+impl Config {
+    // regions returns the unique regions referenced by the config stacks
+    pub fn regions(&self) -> Vec<String> {
+        let mut regions: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|s| s.region.clone().unwrap_or("eu-west-1".to_string()))
+            .collect();
+        regions.sort();
+        regions.dedup();
+        regions
+    }
 }
 
 impl From<config::ConfigLoader> for Config {
     fn from(c: config::ConfigLoader) -> Self {
-        Config {
-            stacks: c.stacks.into_inner(),
+        let project = c.project.into_inner();
+        let mut stacks = c.stacks.into_inner();
+        for s in stacks.iter_mut() {
+            s.project = project.clone();
         }
+
+        Config { stacks, project }
     }
 }
 
 #[derive(Debug, ProvidesStaticType, Default, Clone)]
 struct ConfigLoader {
     pub stacks: RefCell<Vec<stacks::Stack>>,
+    pub project: RefCell<Option<String>>,
 }
 
 impl ConfigLoader {
@@ -71,84 +94,162 @@ impl ConfigLoader {
     }
 }
 
+// unpack returns the group passed to stacks.new, for eg: the result
+// of stacks.options, or the empty group when it is not set
+fn unpack<'v, T: StarlarkValue<'v> + Clone + Default>(
+    value: Option<Value<'v>>,
+    name: &str,
+) -> anyhow::Result<T> {
+    match value {
+        Some(v) => v.downcast_ref::<T>().cloned().ok_or_else(|| {
+            anyhow::Error::msg(format!(
+                "{} must be created with stacks.{}, got {}",
+                name,
+                name,
+                v.get_type()
+            ))
+        }),
+        None => Ok(T::default()),
+    }
+}
+
 #[starlark_module]
 pub fn starlark_stacks_module(builder: &mut GlobalsBuilder) {
     fn new(
         name: String,
         template: String,
         region: String,
-        bucket: Option<String>,
-
-        // depends_on: Option<Vec<String>>,
-        // depends_on: Option<list::ListOf<String>>,
-        values: Option<Value>,
-        parameters: Option<SmallMap<String, String>>,
-        capabilities: Option<list::ListOf<String>>,
-        custom_resources: Option<list::ListOf<String>>,
+        depends_on: Option<list::ListOf<String>>,
+        inputs: Option<Value>,
+        options: Option<Value>,
+        lifecycle: Option<Value>,
         // hook: Option<Value>
 
         // json_values: serde_json::Value,
     ) -> anyhow::Result<stacks::Stack> {
-        let mut stack = stacks::Stack {
+        let inputs: stacks::Inputs = unpack(inputs, "inputs")?;
+        let options: stacks::Options = unpack(options, "options")?;
+        let lifecycle: stacks::Lifecycle = unpack(lifecycle, "lifecycle")?;
+
+        let stack = stacks::Stack {
             name: name,
             template: template,
-            bucket: bucket,
-            values: None,
-            parameters: None,
-            capabilities: None,
+            bucket: options.bucket,
+            values: inputs.values,
+            value_sources: inputs.value_sources,
+            parameters: inputs.parameters,
+            previous_parameters: inputs.previous_parameters,
+            capabilities: options.capabilities,
             region: Some(region),
             exec: None,
-            depends_on: None,
-            custom_resources: None,
+            depends_on: depends_on.map(|d| d.to_vec()),
+            custom_resources: options.custom_resources,
+            tags: options.tags,
+            ttl: lifecycle.ttl,
+            protect_replacement: lifecycle.protect_replacement,
+            role_arn: options.role_arn,
+            notification_arns: options.notification_arns,
+            termination_protection: lifecycle.termination_protection,
+            project: None,
         };
 
-        if let Some(capabilities) = capabilities {
-            let caps: Vec<String> = Some(capabilities)
-                .unwrap()
-                .to_vec()
-                .iter()
-                .map(|s| s.clone())
-                .collect();
-            stack.capabilities = Some(caps);
-        }
+        // if let Some(exec)
+        Ok(stack)
+    }
 
-        // if let Some(depends_on) = depends_on {
-        //     let depends = Some(depends_on)
-        //         .unwrap()
-        //         .to_vec()
-        //         .iter()
-        //         .map(|s| s.clone())
-        //         .collect();
-        //     stack.depends_on = Some(depends);
-        // }
+    // inputs groups the values used to render the template
+    // and the parameters passed to the stack
+    fn inputs(
+        values: Option<Value>,
+        values_files: Option<list::ListOf<String>>,
+        list_merge: Option<String>,
+        parameters: Option<SmallMap<String, Value>>,
+        parameters_file: Option<String>,
+    ) -> anyhow::Result<stacks::Inputs> {
+        let mut inputs = stacks::Inputs::default();
+
+        if let Some(path) = parameters_file {
+            let file = parameters::load_file(&path).map_err(anyhow::Error::msg)?;
+            inputs.parameters = Some(file.values);
+            inputs.previous_parameters = Some(file.use_previous);
+        }
 
         if let Some(parameters) = parameters {
-            let mut params: HashMap<String, String> = HashMap::new();
+            // inline parameters override values from the parameters file
+            let mut params: HashMap<String, serde_json::Value> =
+                inputs.parameters.take().unwrap_or_default();
             for (k, v) in parameters {
-                params.insert(k.to_string(), v.to_string());
+                if let Some(previous) = inputs.previous_parameters.as_mut() {
+                    previous.retain(|p| p != &k);
+                }
+                // values are converted to strings when deploying,
+                // once the template parameter types are known
+                params.insert(k.to_string(), serde_json::to_value(v)?);
             }
 
-            stack.parameters = Some(params);
+            inputs.parameters = Some(params);
         }
 
-        if let Some(vals) = values {
-            let value_str = serde_json::to_string(&vals)?;
-            let values: serde_json::Value = serde_json::from_str(value_str.as_str())?;
-            stack.values = Some(values);
+        if values.is_some() || values_files.is_some() {
+            let strategy = values::ListMerge::try_from(list_merge.as_deref().unwrap_or("replace"))
+                .map_err(anyhow::Error::msg)?;
+            let mut layers = values::Layers::new(strategy);
+
+            // values files are merged in order, inline values are applied last
+            if let Some(files) = values_files {
+                for f in files.to_vec().iter() {
+                    layers.add_file(f).map_err(anyhow::Error::msg)?;
+                }
+            }
+
+            if let Some(vals) = values {
+                let value_str = serde_json::to_string(&vals)?;
+                let values: serde_json::Value = serde_json::from_str(value_str.as_str())?;
+                layers.add(values, values::INLINE_SOURCE);
+            }
+
+            inputs.values = Some(layers.values);
+            inputs.value_sources = Some(layers.sources);
         }
 
-        if let Some(custom_resources) = custom_resources {
-            let crs: Vec<String> = Some(custom_resources)
-                .unwrap() // TODO: handle unwrap
-                .to_vec()
-                .iter()
-                .map(|s| s.clone())
-                .collect();
-            stack.custom_resources = Some(crs);
+        Ok(inputs)
+    }
+
+    // options groups the settings the stack is deployed with
+    fn options(
+        bucket: Option<String>,
+        capabilities: Option<list::ListOf<String>>,
+        custom_resources: Option<list::ListOf<String>>,
+        tags: Option<SmallMap<String, String>>,
+        role_arn: Option<String>,
+        notification_arns: Option<list::ListOf<String>>,
+    ) -> anyhow::Result<stacks::Options> {
+        Ok(stacks::Options {
+            bucket,
+            capabilities: capabilities.map(|c| c.to_vec()),
+            custom_resources: custom_resources.map(|c| c.to_vec()),
+            tags: tags.map(|t| t.into_iter().collect()),
+            role_arn,
+            notification_arns: notification_arns.map(|n| n.to_vec()),
+        })
+    }
+
+    // lifecycle groups how long the stack lives and how it is protected
+    fn lifecycle(
+        ttl: Option<String>,
+        termination_protection: Option<bool>,
+        protect_replacement: Option<list::ListOf<String>>,
+    ) -> anyhow::Result<stacks::Lifecycle> {
+        if let Some(ttl) = &ttl {
+            // validate ttl at evaluation time
+            stacks::parse_ttl(ttl).map_err(anyhow::Error::msg)?;
         }
 
-        // if let Some(exec)
-        Ok(stack)
+        Ok(stacks::Lifecycle {
+            ttl,
+            termination_protection,
+            protect_replacement: protect_replacement.map(|p| p.to_vec()),
+        })
     }
 
     fn add(x: Value, eval: &mut Evaluator) -> anyhow::Result<NoneType> {
@@ -169,6 +270,25 @@ pub fn starlark_stacks_module(builder: &mut GlobalsBuilder) {
 
         Ok(NoneType)
     }
+
+    // project names the project of the config. Stacks are tagged with it,
+    // so orphans only considers the stacks deployed from this config
+    fn project(name: String, eval: &mut Evaluator) -> anyhow::Result<NoneType> {
+        let c = eval
+            .extra
+            .ok_or_else(|| anyhow::Error::msg("failed to set project: evaluation failed"))?
+            .downcast_ref::<ConfigLoader>()
+            .ok_or_else(|| {
+                anyhow::Error::msg("failed to set project: unable to cast ConfigLoader")
+            })?;
+
+        if name.trim().is_empty() {
+            return Err(anyhow::Error::msg("project name can't be empty"));
+        }
+        c.project.replace(Some(name));
+
+        Ok(NoneType)
+    }
 }
 
 #[starlark_module]
@@ -265,7 +385,7 @@ pub fn load_config_from_file(src: String) -> Result<Config, String> {
     let content = src.read()?;
     let ast = AstModule::parse(&src, content, &Dialect::Standard).map_err(|e| e.to_string())?;
     // We build our globals adding some functions we wrote
-    let globals = GlobalsBuilder::new()
+    let globals = GlobalsBuilder::standard()
         .with_struct("stacks", starlark_stacks_module)
         .with_struct("os", os_functions)
         .with_struct("http", http_functions)
@@ -275,6 +395,7 @@ pub fn load_config_from_file(src: String) -> Result<Config, String> {
     // let store = Store::default();
     let config = ConfigLoader {
         stacks: RefCell::new(Vec::new()),
+        project: RefCell::new(None),
     };
 
     let mut eval = Evaluator::new(&module);
@@ -286,10 +407,20 @@ pub fn load_config_from_file(src: String) -> Result<Config, String> {
     Ok(Config::from(config.clone()))
 }
 
+// load_test_config loads a config from its contents, for tests
+#[cfg(test)]
+pub fn load_test_config(contents: &str) -> Config {
+    let tmp_dir = tempdir::TempDir::new("testing").unwrap();
+    let path = tmp_dir.path().join("config.star");
+    std::fs::write(&path, contents).unwrap();
+    load_config_from_file(path.to_string_lossy().to_string()).unwrap()
+}
+
 // Tests
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::template;
     use httpmock::prelude::*;
     use indoc::indoc;
     use std::fs::File;
@@ -300,16 +431,7 @@ mod tests {
     // and returns the path to the directory
     macro_rules! create_test_config {
         (config:$contents:expr) => {{
-            // Create a temporary directory
-            let tmp_dir = TempDir::new("testing").map_err(|e| e.to_string()).unwrap();
-            let tmp_config_path_buf = tmp_dir.path().join("config.star");
-            let path = tmp_config_path_buf.to_string_lossy().to_string();
-
-            // Create the file and write the contents to it
-            let mut tmp_config_file = File::create(&tmp_config_path_buf).unwrap();
-            write!(tmp_config_file, "{}", $contents).unwrap();
-
-            load_config_from_file(path).unwrap()
+            load_test_config($contents)
         }};
     }
 
@@ -466,4 +588,394 @@ mod tests {
             region
         );
     }
+
+    #[test]
+    fn test_stack_tags_and_ttl() {
+        let config = create_test_config!(config: indoc! {r#"
+            stack = stacks.new(
+                name = 'test',
+                region = "eu-west-1",
+                template = "none",
+                options = stacks.options(tags = {"team": "platform"}),
+                lifecycle = stacks.lifecycle(ttl = "72h"),
+            )
+
+            stacks.add(stack)
+        "#});
+
+        let tags = config.stacks[0].tags();
+        assert_eq!(tags.get("team").map(|s| s.as_str()), Some("platform"));
+        assert_eq!(tags.get(stacks::TTL_TAG).map(|s| s.as_str()), Some("72h"));
+        assert_eq!(
+            tags.get(stacks::MANAGED_TAG).map(|s| s.as_str()),
+            Some("true")
+        );
+        assert_eq!(tags.get(stacks::PROJECT_TAG), None);
+        assert_eq!(
+            stacks::parse_ttl("72h").unwrap(),
+            chrono::Duration::hours(72)
+        );
+
+        for ttl in ["", "h", "72", "-1h", "3y"] {
+            assert!(
+                stacks::parse_ttl(ttl).is_err(),
+                "expected ttl [{}] to be invalid",
+                ttl
+            );
+        }
+    }
+
+    #[test]
+    fn test_project() {
+        // the project applies to every stack, wherever it is set
+        let config = create_test_config!(config: indoc! {r#"
+            stacks.add(stacks.new(name = "app", region = "eu-west-1", template = "none"))
+            stacks.project("payments")
+            stacks.add(stacks.new(name = "db", region = "eu-west-1", template = "none"))
+        "#});
+
+        assert_eq!(config.project.as_deref(), Some("payments"));
+        for s in config.stacks.iter() {
+            assert_eq!(
+                s.tags().get(stacks::PROJECT_TAG).map(|p| p.as_str()),
+                Some("payments")
+            );
+        }
+    }
+
+    #[test]
+    fn test_stack_groups() {
+        let config = create_test_config!(config: indoc! {r#"
+            stacks.add(stacks.new(
+                name = 'test',
+                region = "eu-west-1",
+                template = "none",
+                options = stacks.options(
+                    bucket = "templates",
+                    capabilities = ["CAPABILITY_IAM"],
+                    custom_resources = ["SeedFunction"],
+                    role_arn = "arn:aws:iam::123456789012:role/deploy",
+                    notification_arns = ["arn:aws:sns:eu-west-1:123456789012:ops"],
+                ),
+                lifecycle = stacks.lifecycle(termination_protection = True),
+            ))
+        "#});
+
+        let stack = &config.stacks[0];
+        assert_eq!(stack.bucket.as_deref(), Some("templates"));
+        assert_eq!(stack.capabilities, Some(vec!["CAPABILITY_IAM".to_string()]));
+        assert_eq!(
+            stack.custom_resources,
+            Some(vec!["SeedFunction".to_string()])
+        );
+        assert_eq!(
+            stack.role_arn.as_deref(),
+            Some("arn:aws:iam::123456789012:role/deploy")
+        );
+        assert_eq!(stack.notification_arns.as_ref().unwrap().len(), 1);
+        assert_eq!(stack.termination_protection, Some(true));
+        assert!(stack.values.is_none() && stack.parameters.is_none());
+
+        // groups must be created with their stacks function
+        let tmp_dir = TempDir::new("testing").unwrap();
+        let path = tmp_dir.path().join("config.star");
+        std::fs::write(
+            &path,
+            r#"stacks.add(stacks.new(name = "test", region = "eu-west-1", template = "none", options = {"bucket": "templates"}))"#,
+        )
+        .unwrap();
+        let err = match load_config_from_file(path.display().to_string()) {
+            Ok(_) => panic!("expected an options dict to be refused"),
+            Err(e) => e,
+        };
+        assert!(
+            err.contains("options must be created with stacks.options"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_values_files() {
+        let tmp_dir = TempDir::new("testing").map_err(|e| e.to_string()).unwrap();
+        let base = tmp_dir.path().join("base.yaml");
+        let prod = tmp_dir.path().join("prod.yaml");
+
+        let mut f = File::create(&base).unwrap();
+        write!(
+            f,
+            "{}",
+            indoc! {r#"
+            env: base
+            subnets: [a, b]
+            db:
+              size: small
+              port: 5432
+        "#}
+        )
+        .unwrap();
+
+        let mut f = File::create(&prod).unwrap();
+        write!(
+            f,
+            "{}",
+            indoc! {r#"
+            env: prod
+            subnets: [b, c]
+            db:
+              size: large
+        "#}
+        )
+        .unwrap();
+
+        std::env::set_var("BASE_VALUES", base.to_string_lossy().to_string());
+        std::env::set_var("PROD_VALUES", prod.to_string_lossy().to_string());
+
+        let config = create_test_config!(config: indoc! {r#"
+            stack = stacks.new(
+                name = 'test',
+                region = "eu-west-1",
+                template = "none",
+                inputs = stacks.inputs(
+                    values_files = [os.env("BASE_VALUES"), os.env("PROD_VALUES")],
+                    list_merge = "unique",
+                    values = {"db": {"port": 5433}},
+                ),
+            )
+
+            stacks.add(stack)
+        "#});
+
+        let stack = &config.stacks[0];
+        let values = stack.values.as_ref().unwrap();
+        assert_eq!(
+            values,
+            &serde_json::json!({
+                "env": "prod",
+                "subnets": ["a", "b", "c"],
+                "db": {"size": "large", "port": 5433}
+            })
+        );
+
+        let sources = stack.value_sources.as_ref().unwrap();
+        let base = base.to_string_lossy().to_string();
+        let prod = prod.to_string_lossy().to_string();
+        assert_eq!(sources["env"], prod);
+        assert_eq!(sources["subnets"], format!("{}, {}", base, prod));
+        assert_eq!(sources["db.size"], prod);
+        assert_eq!(sources["db.port"], values::INLINE_SOURCE);
+    }
+
+    #[test]
+    fn test_parameters_file() {
+        let tmp_dir = TempDir::new("testing").map_err(|e| e.to_string()).unwrap();
+        let json = tmp_dir.path().join("params.json");
+        let kv = tmp_dir.path().join("params.env");
+
+        let mut f = File::create(&json).unwrap();
+        write!(
+            f,
+            "{}",
+            indoc! {r#"
+            [
+              {"ParameterKey": "Env", "ParameterValue": "prod"},
+              {"ParameterKey": "Size", "ParameterValue": "small"},
+              {"ParameterKey": "Password", "UsePreviousValue": true}
+            ]
+        "#}
+        )
+        .unwrap();
+
+        let mut f = File::create(&kv).unwrap();
+        write!(
+            f,
+            "{}",
+            indoc! {r#"
+            # comment
+            Env=dev
+            Name = "my stack"
+        "#}
+        )
+        .unwrap();
+
+        std::env::set_var("JSON_PARAMS", json.to_string_lossy().to_string());
+        std::env::set_var("KV_PARAMS", kv.to_string_lossy().to_string());
+
+        let config = create_test_config!(config: indoc! {r#"
+            stacks.add(stacks.new(
+                name = 'json',
+                region = "eu-west-1",
+                template = "none",
+                inputs = stacks.inputs(
+                    parameters_file = os.env("JSON_PARAMS"),
+                    parameters = {"Size": "large"},
+                ),
+            ))
+
+            stacks.add(stacks.new(
+                name = 'kv',
+                region = "eu-west-1",
+                template = "none",
+                inputs = stacks.inputs(parameters_file = os.env("KV_PARAMS")),
+            ))
+        "#});
+
+        let params = config.stacks[0].parameters.as_ref().unwrap();
+        assert_eq!(params["Env"], "prod");
+        assert_eq!(params["Size"], "large");
+        assert!(!params.contains_key("Password"));
+        assert_eq!(
+            config.stacks[0].previous_parameters,
+            Some(vec!["Password".to_string()])
+        );
+
+        let params = config.stacks[1].parameters.as_ref().unwrap();
+        assert_eq!(params["Env"], "dev");
+        assert_eq!(params["Name"], "my stack");
+    }
+
+    #[test]
+    fn test_typed_parameters() {
+        let config = create_test_config!(config: indoc! {r#"
+            stacks.add(stacks.new(
+                name = 'test',
+                region = "eu-west-1",
+                template = "none",
+                inputs = stacks.inputs(parameters = {
+                    "Count": 3,
+                    "Enabled": True,
+                    "Subnets": ["subnet-a", "subnet-b"],
+                    "Ports": [80, 443],
+                    "Name": "web",
+                }),
+            ))
+        "#});
+
+        let template = indoc! {r#"
+            Parameters:
+              Count:
+                Type: Number
+              Subnets:
+                Type: List<AWS::EC2::Subnet::Id>
+              Ports:
+                Type: List<Number>
+            Resources:
+              Bucket:
+                Type: AWS::S3::Bucket
+                Properties:
+                  BucketName: !Sub "${Name}-bucket"
+        "#};
+
+        let params = config.stacks[0].parameters.as_ref().unwrap();
+        let resolved = parameters::resolve(params, template).unwrap();
+        assert_eq!(resolved["Count"], "3");
+        assert_eq!(resolved["Enabled"], "true");
+        assert_eq!(resolved["Subnets"], "subnet-a,subnet-b");
+        assert_eq!(resolved["Ports"], "80,443");
+        assert_eq!(resolved["Name"], "web");
+
+        let declared = |param_type: &str| template::TemplateParameter {
+            param_type: param_type.to_string(),
+            ..Default::default()
+        };
+        let value = serde_json::json!("three");
+        assert!(parameters::to_cfn_value("Count", &value, Some(&declared("Number"))).is_err());
+        let value = serde_json::json!(["a", "b"]);
+        assert!(parameters::to_cfn_value("Name", &value, Some(&declared("String"))).is_err());
+    }
+
+    #[test]
+    fn test_protect_replacement() {
+        let config = create_test_config!(config: indoc! {r#"
+            stacks.add(stacks.new(name = "default", region = "eu-west-1", template = "none"))
+            stacks.add(stacks.new(
+                name = "custom",
+                region = "eu-west-1",
+                template = "none",
+                lifecycle = stacks.lifecycle(protect_replacement = ["AWS::Logs::*", "AWS::SQS::Queue"]),
+            ))
+            stacks.add(stacks.new(name = "disabled", region = "eu-west-1", template = "none", lifecycle = stacks.lifecycle(protect_replacement = [])))
+        "#});
+
+        let (default, custom, disabled) = (&config.stacks[0], &config.stacks[1], &config.stacks[2]);
+        assert!(default.is_protected("AWS::RDS::DBInstance"));
+        assert!(!default.is_protected("AWS::SQS::Queue"));
+        assert!(custom.is_protected("AWS::Logs::LogGroup"));
+        assert!(custom.is_protected("AWS::SQS::Queue"));
+        assert!(!custom.is_protected("AWS::S3::Bucket"));
+        assert!(!disabled.is_protected("AWS::S3::Bucket"));
+    }
+
+    #[test]
+    fn test_validate_parameters() {
+        let config = create_test_config!(config: indoc! {r#"
+            stacks.add(stacks.new(
+                name = 'test',
+                region = "eu-west-1",
+                template = """
+                Parameters:
+                  Env:
+                    Type: String
+                    AllowedValues: [dev, prod]
+                  Name:
+                    Type: String
+                    AllowedPattern: "[a-z]+"
+                    MaxLength: 5
+                  Count:
+                    Type: Number
+                    MinValue: 1
+                    MaxValue: 3
+                  Zones:
+                    Type: CommaDelimitedList
+                    AllowedValues: [a, b]
+                  Size:
+                    Type: String
+                    Default: small
+                  Password:
+                    Type: String
+                    NoEcho: true
+                  Vpc:
+                    Type: String
+                """,
+                inputs = stacks.inputs(parameters = {
+                    "Env": "test",
+                    "Name": "Website",
+                    "Count": 5,
+                    "Zones": ["a", "c"],
+                    "Typo": "value",
+                }),
+            ))
+        "#});
+
+        let err = parameters::validate_stack(&config.stacks[0]).unwrap_err();
+        for issue in [
+            "[Typo] is not declared in the template",
+            "[Env] value [test] is not one of the allowed values: dev, prod",
+            "[Name] value [Website] does not match the allowed pattern",
+            "[Name] must be at most 5 characters long",
+            "[Count] value [5] is greater than the maximum value: 3",
+            "[Zones] value [c] is not one of the allowed values",
+            "[Password] is required",
+            "[Vpc] is required",
+        ] {
+            assert!(err.contains(issue), "expected [{}] in: {}", issue, err);
+        }
+        assert!(!err.contains("[Size]"), "unexpected [Size] in: {}", err);
+
+        let stack = &config.stacks[0];
+        let declared = template::parameters(&template::parse(&stack.template).unwrap());
+        assert!(declared["Password"].no_echo);
+        assert_eq!(
+            parameters::missing(stack.parameters.as_ref().unwrap(), &[], &declared),
+            vec!["Password".to_string(), "Vpc".to_string()]
+        );
+        assert_eq!(
+            parameters::missing(
+                stack.parameters.as_ref().unwrap(),
+                &["Password".to_string()],
+                &declared
+            ),
+            vec!["Vpc".to_string()]
+        );
+    }
 }
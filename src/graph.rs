@@ -0,0 +1,274 @@
+use crate::output::Outcome;
+use crate::stacks::Stack;
+use colored::Colorize;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::time::Instant;
+use tokio::task::JoinSet;
+
+// Graph is the dependency graph of the stacks selected for a run.
+// Dependencies on stacks that are not selected are ignored
+pub struct Graph {
+    pub stacks: Vec<Stack>,
+    // indices of the stacks each stack waits for
+    waits_for: Vec<Vec<usize>>,
+}
+
+// StackRun is the result of running a command on a single stack
+pub struct StackRun {
+    pub stack: Stack,
+    pub result: Result<Outcome, String>,
+    pub started: Instant,
+}
+
+impl Graph {
+    // new builds the graph of the given stacks. Stacks wait for their
+    // dependencies, or for their dependents when reverse is set (delete)
+    pub fn new(stacks: Vec<Stack>, reverse: bool) -> Result<Self, String> {
+        let waits_for = stacks
+            .iter()
+            .map(|s| {
+                stacks
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, o)| match reverse {
+                        false => o.is_dependency_of(s),
+                        true => s.is_dependency_of(o),
+                    })
+                    .map(|(i, _)| i)
+                    .collect()
+            })
+            .collect();
+
+        let graph = Graph { stacks, waits_for };
+        let order = graph.order();
+        if order.len() != graph.stacks.len() {
+            let cycle: Vec<String> = (0..graph.stacks.len())
+                .filter(|i| !order.contains(i))
+                .map(|i| graph.stacks[i].name.clone())
+                .collect();
+            return Err(format!(
+                "dependency cycle between stacks: {}",
+                cycle.join(", ")
+            ));
+        }
+
+        Ok(graph)
+    }
+
+    // order returns the stacks in the order they are processed one at a
+    // time. Stacks that are part of a cycle are left out
+    pub fn order(&self) -> Vec<usize> {
+        let mut order = Vec::new();
+        let mut done = HashSet::new();
+        while let Some(i) = self.ready(&done, &done).into_iter().next() {
+            done.insert(i);
+            order.push(i);
+        }
+        order
+    }
+
+    // ready returns the stacks that have not started yet
+    // and whose stacks they wait for are done
+    fn ready(&self, done: &HashSet<usize>, started: &HashSet<usize>) -> Vec<usize> {
+        (0..self.stacks.len())
+            .filter(|i| !started.contains(i))
+            .filter(|i| self.waits_for[*i].iter().all(|w| done.contains(w)))
+            .collect()
+    }
+
+    // run runs a command on every stack of the graph, up to parallel
+    // stacks at a time. A stack starts once the stacks it waits for
+    // succeeded, stacks waiting for a failed stack are skipped. Unless
+    // keep_going is set, no stack is started after a failure
+    pub async fn run<F, Fut>(self, parallel: usize, keep_going: bool, f: F) -> Vec<StackRun>
+    where
+        F: Fn(Stack) -> Fut,
+        Fut: Future<Output = Result<Outcome, String>> + Send + 'static,
+    {
+        let mut runs = Vec::new();
+        let mut done = HashSet::new();
+        let mut started = HashSet::new();
+        // stacks that failed or were skipped
+        let mut failed = HashSet::new();
+        let mut tasks = JoinSet::new();
+        let mut running = HashMap::new();
+
+        loop {
+            // skipping a stack may block the stacks waiting for it
+            while let Some((i, blocker)) = self.blocked(&failed, &started) {
+                log::warn!(
+                    "[{}] {} [{}] {}",
+                    self.stacks[i].name.cyan(),
+                    "skipped, waits for".yellow(),
+                    self.stacks[blocker].name.cyan(),
+                    "which did not succeed".yellow()
+                );
+                started.insert(i);
+                failed.insert(i);
+                runs.push(self.skipped(i));
+            }
+
+            if keep_going || failed.is_empty() {
+                for i in self.ready(&done, &started) {
+                    if tasks.len() >= parallel.max(1) {
+                        break;
+                    }
+
+                    started.insert(i);
+                    let fut = f(self.stacks[i].clone());
+                    let handle = tasks.spawn(fut);
+                    running.insert(handle.id(), (i, Instant::now()));
+                }
+            }
+
+            let Some(joined) = tasks.join_next_with_id().await else {
+                break;
+            };
+
+            let (id, result) = match joined {
+                Ok((id, result)) => (id, result),
+                Err(e) => (e.id(), Err(format!("stack task failed: {}", e))),
+            };
+
+            // note: unwrap is fine here, every spawned task is recorded
+            let (i, started_at) = running.remove(&id).unwrap();
+            if result.is_ok() {
+                done.insert(i);
+            } else {
+                failed.insert(i);
+            }
+
+            runs.push(StackRun {
+                stack: self.stacks[i].clone(),
+                result,
+                started: started_at,
+            });
+        }
+
+        // stacks left after a failure were never started
+        for i in 0..self.stacks.len() {
+            if !started.contains(&i) {
+                log::warn!(
+                    "[{}] {}",
+                    self.stacks[i].name.cyan(),
+                    "skipped after an earlier failure, use --keep-going to continue".yellow()
+                );
+                runs.push(self.skipped(i));
+            }
+        }
+
+        runs
+    }
+
+    // blocked returns a stack that has not started and waits
+    // for a failed stack, along with the failed stack
+    fn blocked(&self, failed: &HashSet<usize>, started: &HashSet<usize>) -> Option<(usize, usize)> {
+        (0..self.stacks.len())
+            .filter(|i| !started.contains(i))
+            .find_map(|i| {
+                self.waits_for[i]
+                    .iter()
+                    .find(|w| failed.contains(*w))
+                    .map(|w| (i, *w))
+            })
+    }
+
+    fn skipped(&self, i: usize) -> StackRun {
+        StackRun {
+            stack: self.stacks[i].clone(),
+            result: Ok(Outcome::Skipped),
+            started: Instant::now(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::output;
+    use indoc::indoc;
+
+    fn load_stacks(config: &str) -> Vec<Stack> {
+        config::load_test_config(config).stacks
+    }
+
+    #[test]
+    fn test_stack_graph() {
+        let stacks = load_stacks(indoc! {r#"
+            stacks.add(stacks.new(name = "app", region = "eu-west-1", template = "none", depends_on = ["network", "db"]))
+            stacks.add(stacks.new(name = "db", region = "eu-west-1", template = "none", depends_on = ["network"]))
+            stacks.add(stacks.new(name = "network", region = "eu-west-1", template = "none"))
+            stacks.add(stacks.new(name = "dns", region = "eu-west-1", template = "none", depends_on = ["external"]))
+        "#});
+        assert_eq!(
+            stacks[0].depends_on,
+            Some(vec!["network".to_string(), "db".to_string()])
+        );
+
+        let names = |g: &Graph| -> Vec<String> {
+            g.order()
+                .iter()
+                .map(|i| g.stacks[*i].name.clone())
+                .collect()
+        };
+
+        // dependencies outside the selection are ignored
+        let graph = Graph::new(stacks.clone(), false).unwrap();
+        assert_eq!(names(&graph), vec!["network", "db", "app", "dns"]);
+
+        let graph = Graph::new(stacks.clone(), true).unwrap();
+        assert_eq!(names(&graph), vec!["app", "db", "network", "dns"]);
+
+        // dependents of a failed stack are skipped, other stacks only
+        // keep running with keep_going
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let run = |keep_going: bool| -> Vec<(String, &str)> {
+            let graph = Graph::new(stacks.clone(), false).unwrap();
+            let runs = runtime.block_on(graph.run(1, keep_going, |stack| async move {
+                match stack.name.as_str() {
+                    "db" => Err("failed".to_string()),
+                    _ => Ok(output::Outcome::Created),
+                }
+            }));
+            let mut results: Vec<(String, &str)> = runs
+                .into_iter()
+                .map(|r| {
+                    let outcome = r.result.unwrap_or(output::Outcome::Failed);
+                    (r.stack.name, outcome.as_str())
+                })
+                .collect();
+            results.sort();
+            results
+        };
+
+        let expected = |results: [(&str, &'static str); 4]| -> Vec<(String, &str)> {
+            results.iter().map(|(n, r)| (n.to_string(), *r)).collect()
+        };
+        assert_eq!(
+            run(false),
+            expected([
+                ("app", "skipped"),
+                ("db", "failed"),
+                ("dns", "skipped"),
+                ("network", "created")
+            ])
+        );
+        assert_eq!(
+            run(true),
+            expected([
+                ("app", "skipped"),
+                ("db", "failed"),
+                ("dns", "created"),
+                ("network", "created")
+            ])
+        );
+
+        let cyclic = load_stacks(indoc! {r#"
+            stacks.add(stacks.new(name = "a", region = "eu-west-1", template = "none", depends_on = ["b"]))
+            stacks.add(stacks.new(name = "b", region = "eu-west-1", template = "none", depends_on = ["a"]))
+        "#});
+        assert!(Graph::new(cyclic, false).is_err());
+    }
+}
@@ -1,12 +1,23 @@
 use colored::Colorize;
-use env_logger::Builder;
+use env_logger::{Builder, Target, WriteStyle};
 use log;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
+
+use crate::progress;
 
 pub fn init() {
+    // log lines are written around the progress lines of stacks, colors
+    // are kept as long as stderr is a terminal
+    let style = match std::io::stderr().is_terminal() {
+        true => WriteStyle::Always,
+        false => WriteStyle::Never,
+    };
+
     Builder::new()
         .filter(None, log::LevelFilter::Info)
         .parse_env("KLOI_LOG")
+        .target(Target::Pipe(Box::new(progress::Logs)))
+        .write_style(style)
         .format(|buf, record| {
             let level = match record.level() {
                 log::Level::Error => "error".red(),
@@ -1,9 +1,15 @@
 mod cli;
 mod config;
+mod graph;
 mod logger;
+mod output;
+mod parameters;
+mod progress;
 mod stacks;
+mod template;
+mod values;
 
-use clap::Command;
+use clap::{arg, Command};
 use cli::*;
 use colored::Colorize;
 
@@ -31,16 +37,46 @@ fn root_command() -> Command {
     Command::new(APP_NAME)
         .version(VERSION)
         .about(about())
+        // output format, applies to every subcommand
+        .arg(
+            arg!(-o --output <FORMAT> "output format, json and yaml print a report on stdout and logs on stderr")
+                .global(true)
+                .value_parser(["text", "json", "yaml"])
+                .default_value("text"),
+        )
+        // how long stack operations are watched, applies to every subcommand
+        .arg(
+            arg!(--timeout <DURATION> "time to watch a stack operation before offering to cancel it, for eg: 30m")
+                .global(true),
+        )
         // add apply command
         .subcommand(cli::apply::command())
         // add delete command
         .subcommand(cli::delete::command())
         // add status command
         .subcommand(cli::status::command())
+        // add diff command
+        .subcommand(cli::diff::command())
+        // add drift command
+        .subcommand(cli::drift::command())
         // add show command
         .subcommand(cli::show::command())
         // add check command
         .subcommand(cli::check::command())
+        // add plan command
+        .subcommand(cli::plan::command())
+        // add execute command
+        .subcommand(cli::execute::command())
+        // add gc command
+        .subcommand(cli::gc::command())
+        // add import command
+        .subcommand(cli::import::command())
+        // add adopt command
+        .subcommand(cli::adopt::command())
+        // add wait command
+        .subcommand(cli::wait::command())
+        // add orphans command
+        .subcommand(cli::orphans::command())
         // add completions command
         .subcommand(cli::completions::command())
 }
@@ -50,20 +86,59 @@ async fn main() -> Result<(), String> {
     logger::init();
     // define command
     let matches = root_command().get_matches();
+
+    // set the output format before running the command
+    // note: unwrap is fine here, the flag has a default value
+    let format = matches.get_one::<String>("output").unwrap();
+    output::init(output::Format::try_from(format.as_str())?);
+
+    if let Some(timeout) = matches.get_one::<String>("timeout") {
+        let timeout = stacks::parse_ttl(timeout)
+            .ok()
+            .and_then(|t| t.to_std().ok())
+            .ok_or_else(|| {
+                format!(
+                    "invalid timeout [{}], expected a positive number followed by s, m, h, d or w",
+                    timeout
+                )
+            })?;
+        utils::set_timeout(timeout);
+    }
+
+    // ctrl-c offers to cancel the stack operation being watched
+    utils::handle_interrupts();
+
     let r = match matches.subcommand() {
         Some(("apply", sub_matches)) => apply::handle(sub_matches).await,
         Some(("delete", sub_matches)) => delete::handle(sub_matches).await,
         Some(("status", sub_matches)) => status::handle(sub_matches).await,
+        Some(("diff", sub_matches)) => diff::handle(sub_matches).await,
+        Some(("drift", sub_matches)) => drift::handle(sub_matches).await,
         Some(("show", sub_matches)) => show::handle(sub_matches).await,
         Some(("check", sub_matches)) => check::handle(sub_matches).await,
+        Some(("plan", sub_matches)) => plan::handle(sub_matches).await,
+        Some(("execute", sub_matches)) => execute::handle(sub_matches).await,
+        Some(("gc", sub_matches)) => gc::handle(sub_matches).await,
+        Some(("import", sub_matches)) => import::handle(sub_matches).await,
+        Some(("adopt", sub_matches)) => adopt::handle(sub_matches).await,
+        Some(("wait", sub_matches)) => wait::handle(sub_matches).await,
+        Some(("orphans", sub_matches)) => orphans::handle(sub_matches).await,
         Some(("completions", sub_matches)) => completions::handle(sub_matches, root_command()),
         _ => root_command().print_help().map_err(|e| e.to_string()),
     };
 
+    // commands that don't write a report still print a document with
+    // the result, except for completions which writes a script
+    let command = matches.subcommand_name().unwrap_or(APP_NAME);
     if let Err(e) = r {
+        output::fail(command, &e);
         log::error!("{}", e.red());
         std::process::exit(1);
     }
 
+    if command != "completions" {
+        output::finish(command);
+    }
+
     Ok(())
 }
@@ -0,0 +1,461 @@
+use crate::stacks;
+use colored::{ColoredString, Colorize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+// version of the machine readable document, bumped on breaking changes
+pub const SCHEMA_VERSION: u32 = 1;
+
+// Format is the output format selected with the global --output flag
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+    Yaml,
+}
+
+impl TryFrom<&str> for Format {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "yaml" => Ok(Format::Yaml),
+            other => Err(format!(
+                "unknown output format [{}], expected text, json or yaml",
+                other
+            )),
+        }
+    }
+}
+
+static FORMAT: OnceLock<Format> = OnceLock::new();
+static EMITTED: AtomicBool = AtomicBool::new(false);
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+// init sets the output format for the process, it can only be set once
+pub fn init(format: Format) {
+    // keep color codes out of the document
+    if format != Format::Text {
+        colored::control::set_override(false);
+    }
+    let _ = FORMAT.set(format);
+    STARTED.get_or_init(Instant::now);
+}
+
+pub fn format() -> Format {
+    *FORMAT.get().unwrap_or(&Format::Text)
+}
+
+pub fn is_text() -> bool {
+    format() == Format::Text
+}
+
+// human prints human readable output. It goes to stdout in text mode and to
+// stderr otherwise, so stdout only carries the machine readable document
+macro_rules! human {
+    ($($arg:tt)*) => {
+        if $crate::output::is_text() {
+            println!($($arg)*)
+        } else {
+            eprintln!($($arg)*)
+        }
+    };
+}
+
+pub(crate) use human;
+
+// Report is the document printed on stdout by every command when
+// --output is json or yaml
+#[derive(Debug, Serialize)]
+pub struct Report {
+    // schema version, see SCHEMA_VERSION
+    pub version: u32,
+    // name of the command that produced the report, for eg: apply
+    pub command: String,
+    pub success: bool,
+    // error that stopped the command, if any
+    pub error: Option<String>,
+    pub duration_ms: u64,
+    // one entry per stack the command worked on
+    pub stacks: Vec<StackResult>,
+}
+
+// StackResult is the result of a command for a single stack. Fields
+// that don't apply to a command are left out of the document
+#[derive(Debug, Default, Serialize)]
+pub struct StackResult {
+    pub name: String,
+    pub region: String,
+    // what the command did with the stack (apply, adopt, delete and check)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Outcome>,
+    // cloudformation stack status, for eg: update_complete
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_reason: Option<String>,
+    // rfc3339 time of the last update, or creation if never updated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_updated: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub termination_protection: Option<bool>,
+    // stack drift status, for eg: in_sync
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drift: Option<String>,
+    // deployed parameters, NoEcho values are masked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outputs: Option<Vec<StackOutput>>,
+    // rendered template (show)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    // values and their source (show --explain-values)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub values: Option<Vec<ValueSource>>,
+    // validation and lint findings (check)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issues: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
+// Outcome is what a command did with a stack
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Created,
+    Updated,
+    Imported,
+    Unchanged,
+    Skipped,
+    Declined,
+    Deleted,
+    Valid,
+    Invalid,
+    Failed,
+}
+
+// StackOutput is an output of a deployed stack
+#[derive(Debug, Serialize)]
+pub struct StackOutput {
+    pub key: String,
+    pub value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub export_name: Option<String>,
+}
+
+// ValueSource is a template value and the values layer it was taken from
+#[derive(Debug, Serialize)]
+pub struct ValueSource {
+    pub key: String,
+    pub value: serde_json::Value,
+    pub source: String,
+}
+
+impl StackResult {
+    // from_outcome builds the entry of a stack that a command
+    // worked on, timed from the given instant
+    pub fn from_outcome(
+        stack: &stacks::Stack,
+        outcome: &Result<Outcome, String>,
+        started: Instant,
+    ) -> Self {
+        StackResult {
+            name: stack.name.clone(),
+            region: stack.region.clone().unwrap_or("eu-west-1".to_string()),
+            result: Some(*outcome.as_ref().unwrap_or(&Outcome::Failed)),
+            error: outcome.as_ref().err().cloned(),
+            duration_ms: Some(started.elapsed().as_millis() as u64),
+            ..Default::default()
+        }
+    }
+}
+
+impl Report {
+    pub fn new(command: &str) -> Self {
+        Report {
+            version: SCHEMA_VERSION,
+            command: command.to_string(),
+            success: true,
+            error: None,
+            duration_ms: 0,
+            stacks: Vec::new(),
+        }
+    }
+
+    // emit prints the report in the selected format, along with the
+    // result of the command. Nothing is printed in text mode
+    pub fn emit(mut self, result: &Result<(), String>) -> Result<(), String> {
+        if is_text() {
+            return Ok(());
+        }
+
+        // durations count from the start of the process
+        self.duration_ms = STARTED.get_or_init(Instant::now).elapsed().as_millis() as u64;
+        if let Err(e) = result {
+            self.success = false;
+            self.error = Some(e.clone());
+        }
+
+        if self
+            .stacks
+            .iter()
+            .any(|s| s.result == Some(Outcome::Failed))
+        {
+            self.success = false;
+        }
+
+        let doc = match format() {
+            Format::Yaml => serde_yaml::to_string(&self).map_err(|e| e.to_string())?,
+            _ => serde_json::to_string_pretty(&self).map_err(|e| e.to_string())?,
+        };
+
+        println!("{}", doc.trim_end());
+        EMITTED.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    // print_summary prints a table of the stacks the command worked on,
+    // followed by the errors of the failed stacks. Text mode only
+    pub fn print_summary(&self) {
+        if !is_text() {
+            return;
+        }
+
+        let header = ["stack", "region", "result", "duration"];
+        let rows: Vec<[String; 4]> = self
+            .stacks
+            .iter()
+            .map(|s| {
+                [
+                    s.name.clone(),
+                    s.region.clone(),
+                    s.result.map(|r| r.as_str()).unwrap_or("-").to_string(),
+                    s.duration_ms
+                        .map(format_duration)
+                        .unwrap_or("-".to_string()),
+                ]
+            })
+            .collect();
+
+        let lines = table(&header, &rows, |r, i, cell| {
+            match (i, self.stacks[r].result) {
+                (0, _) => cell.cyan().to_string(),
+                (2, Some(o)) => o.colored(&cell).to_string(),
+                _ => cell.truecolor(96, 96, 96).to_string(),
+            }
+        });
+
+        println!();
+        for line in lines.iter() {
+            println!("{}", line);
+        }
+
+        let count = |outcomes: &[Outcome]| {
+            self.stacks
+                .iter()
+                .filter(|s| s.result.is_some_and(|r| outcomes.contains(&r)))
+                .count()
+        };
+        println!(
+            "\n{} succeeded, {} unchanged, {} failed, {} skipped",
+            count(&[
+                Outcome::Created,
+                Outcome::Updated,
+                Outcome::Imported,
+                Outcome::Deleted
+            ])
+            .to_string()
+            .green(),
+            count(&[Outcome::Unchanged, Outcome::Declined])
+                .to_string()
+                .truecolor(96, 96, 96),
+            count(&[Outcome::Failed]).to_string().red(),
+            count(&[Outcome::Skipped]).to_string().yellow()
+        );
+
+        for s in self.stacks.iter() {
+            if let Some(e) = &s.error {
+                println!("[{}] {}", s.name.cyan(), e.red());
+            }
+        }
+    }
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Created => "created",
+            Outcome::Updated => "updated",
+            Outcome::Imported => "imported",
+            Outcome::Unchanged => "unchanged",
+            Outcome::Skipped => "skipped",
+            Outcome::Declined => "declined",
+            Outcome::Deleted => "deleted",
+            Outcome::Valid => "valid",
+            Outcome::Invalid => "invalid",
+            Outcome::Failed => "failed",
+        }
+    }
+
+    fn colored(&self, s: &str) -> ColoredString {
+        match self {
+            Outcome::Failed | Outcome::Invalid => s.red(),
+            Outcome::Skipped => s.yellow(),
+            Outcome::Unchanged | Outcome::Declined => s.truecolor(96, 96, 96),
+            _ => s.green(),
+        }
+    }
+}
+
+// table lays rows out in aligned columns, with the header in bold when
+// one is given. Column widths are computed before styling, since color
+// codes would otherwise count towards the padding. style is called with
+// the row and column index of every padded cell
+pub fn table<R, F>(header: &[&str], rows: &[R], style: F) -> Vec<String>
+where
+    R: AsRef<[String]>,
+    F: Fn(usize, usize, String) -> String,
+{
+    let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+    for row in rows.iter() {
+        for (i, col) in row.as_ref().iter().enumerate() {
+            match widths.get_mut(i) {
+                Some(w) => *w = (*w).max(col.len()),
+                None => widths.push(col.len()),
+            }
+        }
+    }
+
+    let pad = |s: &str, i: usize| format!("{:<width$}", s, width = widths[i]);
+
+    let mut lines = Vec::new();
+    if !header.is_empty() {
+        let cols: Vec<String> = header
+            .iter()
+            .enumerate()
+            .map(|(i, h)| pad(&h.to_uppercase(), i).bold().to_string())
+            .collect();
+        lines.push(cols.join("  ").trim_end().to_string());
+    }
+
+    for (r, row) in rows.iter().enumerate() {
+        let cols: Vec<String> = row
+            .as_ref()
+            .iter()
+            .enumerate()
+            .map(|(i, col)| style(r, i, pad(col, i)))
+            .collect();
+        lines.push(cols.join("  ").trim_end().to_string());
+    }
+
+    lines
+}
+
+// format_duration renders a duration in milliseconds, for eg: 2m 5s
+pub fn format_duration(ms: u64) -> String {
+    let secs = ms / 1000;
+    match secs {
+        0..=59 => format!("{}s", secs),
+        _ => format!("{}m {}s", secs / 60, secs % 60),
+    }
+}
+
+// exit code of commands that found differences to review, such as diff
+// and drift. Errors exit with 1, so pipelines can tell them apart
+pub const DIFFERENCES_FOUND: i32 = 2;
+
+// differences reports the differences a command found and exits
+// with DIFFERENCES_FOUND
+pub fn differences(command: &str, message: &str) -> ! {
+    fail(command, message);
+    log::warn!("{}", message.yellow());
+    std::process::exit(DIFFERENCES_FOUND);
+}
+
+// fail emits a report for a command that failed before producing
+// its own report, so scripts always receive a document
+pub fn fail(command: &str, error: &str) {
+    if is_text() || EMITTED.load(Ordering::SeqCst) {
+        return;
+    }
+
+    if let Err(e) = Report::new(command).emit(&Err(error.to_string())) {
+        log::error!("failed to write report: {}", e);
+    }
+}
+
+// finish emits an empty report for commands that don't produce their own
+pub fn finish(command: &str) {
+    if is_text() || EMITTED.load(Ordering::SeqCst) {
+        return;
+    }
+
+    if let Err(e) = Report::new(command).emit(&Ok(())) {
+        log::error!("failed to write report: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let rows = vec![
+            [
+                "app".to_string(),
+                "eu-west-1".to_string(),
+                "created".to_string(),
+            ],
+            [
+                "database".to_string(),
+                "us-east-1".to_string(),
+                "-".to_string(),
+            ],
+        ];
+        let lines = table(&[], &rows, |r, i, cell| match (r, i) {
+            (1, 2) => cell.replace('-', "none"),
+            _ => cell,
+        });
+
+        // every column is padded to its widest cell, the last one is trimmed
+        assert_eq!(
+            lines,
+            vec!["app       eu-west-1  created", "database  us-east-1  none",]
+        );
+
+        // the header counts towards the widths
+        let lines = table(&["stack", "region", "result"], &rows, |_, _, cell| cell);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1], "app       eu-west-1  created");
+    }
+
+    #[test]
+    fn test_output_report() {
+        let mut report = Report::new("apply");
+        report.stacks.push(StackResult {
+            name: "test".to_string(),
+            region: "eu-west-1".to_string(),
+            result: Some(Outcome::Unchanged),
+            ..Default::default()
+        });
+
+        let doc = serde_json::to_value(&report).unwrap();
+        assert_eq!(doc["version"], SCHEMA_VERSION);
+        assert_eq!(doc["command"], "apply");
+        assert_eq!(doc["stacks"][0]["result"], "unchanged");
+        // fields that don't apply to a command are left out
+        assert!(doc["stacks"][0].get("template").is_none());
+    }
+}
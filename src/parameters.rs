@@ -0,0 +1,375 @@
+use crate::config::Source;
+use crate::stacks;
+use crate::template;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+// shown in place of NoEcho values in errors
+const MASKED: &str = "****";
+
+// ParameterFile holds the parameters read from a cloudformation parameters file
+#[derive(Debug, Default)]
+pub struct ParameterFile {
+    pub values: HashMap<String, Value>,
+    // keys that should keep the value of the deployed stack
+    pub use_previous: Vec<String>,
+}
+
+// Parameter entry in the aws cli json format, for eg:
+// [{"ParameterKey": "Env", "ParameterValue": "prod"}]
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct CliParameter {
+    parameter_key: String,
+    parameter_value: Option<String>,
+    use_previous_value: Option<bool>,
+}
+
+// load_file reads a parameters file in either the aws cli json
+// format or the key=value format (one parameter per line)
+pub fn load_file(path: &str) -> Result<ParameterFile, String> {
+    let content = path.to_string().read()?;
+    let parsed = if content.trim_start().starts_with('[') {
+        parse_json(&content)
+    } else {
+        parse_key_values(&content)
+    };
+
+    parsed.map_err(|e| format!("failed to parse parameters file [{}]: {}", path, e))
+}
+
+fn parse_json(content: &str) -> Result<ParameterFile, String> {
+    let entries: Vec<CliParameter> = serde_json::from_str(content).map_err(|e| e.to_string())?;
+
+    let mut file = ParameterFile::default();
+    for p in entries {
+        if p.use_previous_value.unwrap_or(false) {
+            file.use_previous.push(p.parameter_key);
+            continue;
+        }
+
+        let value = p.parameter_value.ok_or_else(|| {
+            format!(
+                "parameter [{}] requires a ParameterValue or UsePreviousValue",
+                p.parameter_key
+            )
+        })?;
+        file.values.insert(p.parameter_key, Value::String(value));
+    }
+
+    Ok(file)
+}
+
+fn parse_key_values(content: &str) -> Result<ParameterFile, String> {
+    let mut file = ParameterFile::default();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected key=value, got [{}]", i + 1, line))?;
+
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+        file.values
+            .insert(key.trim().to_string(), Value::String(value.to_string()));
+    }
+
+    Ok(file)
+}
+
+// resolve converts the stack parameter values to the strings cloudformation
+// expects, following the parameter types declared by the rendered template
+pub fn resolve(
+    values: &HashMap<String, Value>,
+    template: &str,
+) -> Result<HashMap<String, String>, String> {
+    let declared = match template::parse(template) {
+        Ok(t) => template::parameters(&t),
+        Err(e) => {
+            log::debug!("converting parameters without template types: {}", e);
+            HashMap::new()
+        }
+    };
+
+    values
+        .iter()
+        .map(|(k, v)| Ok((k.clone(), to_cfn_value(k, v, declared.get(k))?)))
+        .collect()
+}
+
+// to_cfn_value converts a parameter value to its cloudformation string form,
+// following the declared parameter type. Lists are converted to
+// comma-delimited strings. NoEcho values are left out of errors
+pub fn to_cfn_value(
+    key: &str,
+    value: &Value,
+    declared: Option<&template::TemplateParameter>,
+) -> Result<String, String> {
+    let shown = match declared.is_some_and(|d| d.no_echo) {
+        true => MASKED.to_string(),
+        false => value.to_string(),
+    };
+    let err = |expected: &str| format!("parameter [{}] expects {}, got: {}", key, expected, shown);
+
+    let scalar = |v: &Value| -> Result<String, String> {
+        match v {
+            Value::String(s) => Ok(s.clone()),
+            Value::Number(n) => Ok(n.to_string()),
+            Value::Bool(b) => Ok(b.to_string()),
+            _ => Err(err("a string, number, bool or list of those")),
+        }
+    };
+
+    let number = |v: &Value| -> Result<String, String> {
+        match v {
+            Value::Number(n) => Ok(n.to_string()),
+            Value::String(s) if s.trim().parse::<f64>().is_ok() => Ok(s.clone()),
+            _ => Err(err("a number")),
+        }
+    };
+
+    let param_type = declared.map(|d| d.param_type.as_str()).unwrap_or_default();
+    let is_list = param_type == "CommaDelimitedList"
+        || param_type.starts_with("List<")
+        || param_type.starts_with("AWS::SSM::Parameter::Value<List<");
+
+    match value {
+        Value::Array(_) if !param_type.is_empty() && !is_list => {
+            Err(err(&format!("a single {} value", param_type)))
+        }
+        Value::Array(items) => {
+            let convert: &dyn Fn(&Value) -> Result<String, String> = if param_type == "List<Number>"
+            {
+                &number
+            } else {
+                &scalar
+            };
+            let items = items
+                .iter()
+                .map(convert)
+                .collect::<Result<Vec<String>, String>>()?;
+            Ok(items.join(","))
+        }
+        v if param_type == "Number" => number(v),
+        v => scalar(v),
+    }
+}
+
+// validate checks the stack parameters against the Parameters section
+// of the rendered template, returning every problem found
+pub fn validate(
+    values: &HashMap<String, Value>,
+    use_previous: &[String],
+    template: &HashMap<String, template::TemplateParameter>,
+) -> Vec<String> {
+    let mut issues = Vec::new();
+
+    let mut unknown: Vec<&String> = values
+        .keys()
+        .chain(use_previous.iter())
+        .filter(|k| !template.contains_key(*k))
+        .collect();
+    unknown.sort();
+    for k in unknown {
+        issues.push(format!("parameter [{}] is not declared in the template", k));
+    }
+
+    for name in missing(values, use_previous, template) {
+        issues.push(format!(
+            "parameter [{}] is required, the template declares no default",
+            name
+        ));
+    }
+
+    let mut names: Vec<&String> = template.keys().collect();
+    names.sort();
+    for name in names {
+        let declared = &template[name];
+        let Some(value) = values.get(name) else {
+            continue;
+        };
+
+        match to_cfn_value(name, value, Some(declared)) {
+            Ok(v) => issues.extend(check_constraints(name, &v, declared)),
+            Err(e) => issues.push(e),
+        }
+    }
+
+    issues
+}
+
+// missing returns the template parameters that have no default
+// and are not set by the stack, sorted by name
+pub fn missing(
+    values: &HashMap<String, Value>,
+    use_previous: &[String],
+    template: &HashMap<String, template::TemplateParameter>,
+) -> Vec<String> {
+    let mut names: Vec<String> = template
+        .iter()
+        .filter(|(k, p)| {
+            p.default.is_none() && !values.contains_key(*k) && !use_previous.contains(k)
+        })
+        .map(|(k, _)| k.clone())
+        .collect();
+    names.sort();
+    names
+}
+
+// check_constraints checks a converted parameter value against the
+// AllowedValues, AllowedPattern, length and numeric bounds of the template
+fn check_constraints(
+    name: &str,
+    value: &str,
+    declared: &template::TemplateParameter,
+) -> Vec<String> {
+    let mut issues = Vec::new();
+    let is_list =
+        declared.param_type == "CommaDelimitedList" || declared.param_type.starts_with("List<");
+
+    // list parameters are checked per item
+    let items: Vec<&str> = if is_list {
+        value.split(',').map(|v| v.trim()).collect()
+    } else {
+        vec![value]
+    };
+
+    for item in items {
+        let shown = match declared.no_echo {
+            true => MASKED,
+            false => item,
+        };
+
+        if let Some(allowed) = &declared.allowed_values {
+            if !allowed.iter().any(|a| a == item) {
+                issues.push(format!(
+                    "parameter [{}] value [{}] is not one of the allowed values: {}",
+                    name,
+                    shown,
+                    allowed.join(", ")
+                ));
+            }
+        }
+
+        if let Some(pattern) = &declared.allowed_pattern {
+            // cloudformation patterns must match the entire value
+            match Regex::new(&format!("^(?:{})$", pattern)) {
+                Ok(re) if !re.is_match(item) => issues.push(format!(
+                    "parameter [{}] value [{}] does not match the allowed pattern: {}",
+                    name, shown, pattern
+                )),
+                Ok(_) => {}
+                Err(e) => log::debug!("skipping AllowedPattern for [{}]: {}", name, e),
+            }
+        }
+
+        let len = item.chars().count() as u64;
+        if declared.min_length.is_some_and(|min| len < min) {
+            issues.push(format!(
+                "parameter [{}] must be at least {} characters long",
+                name,
+                declared.min_length.unwrap_or_default()
+            ));
+        }
+
+        if declared.max_length.is_some_and(|max| len > max) {
+            issues.push(format!(
+                "parameter [{}] must be at most {} characters long",
+                name,
+                declared.max_length.unwrap_or_default()
+            ));
+        }
+
+        if let Ok(n) = item.parse::<f64>() {
+            if declared.min_value.is_some_and(|min| n < min) {
+                issues.push(format!(
+                    "parameter [{}] value [{}] is less than the minimum value: {}",
+                    name,
+                    shown,
+                    declared.min_value.unwrap_or_default()
+                ));
+            }
+
+            if declared.max_value.is_some_and(|max| n > max) {
+                issues.push(format!(
+                    "parameter [{}] value [{}] is greater than the maximum value: {}",
+                    name,
+                    shown,
+                    declared.max_value.unwrap_or_default()
+                ));
+            }
+        }
+    }
+
+    issues
+}
+
+// validate_stack renders the stack template and validates the stack
+// parameters against it. Templates that cannot be parsed are skipped
+// and left for cloudformation to report on
+pub fn validate_stack(s: &stacks::Stack) -> Result<(), String> {
+    let rendered = s.generate_template()?;
+    let declared = match template::parse(&rendered) {
+        Ok(t) => template::parameters(&t),
+        Err(e) => {
+            log::warn!("[{}] skipping parameter validation: {}", s.name, e);
+            return Ok(());
+        }
+    };
+
+    let issues = validate(
+        &s.parameters.clone().unwrap_or_default(),
+        &s.previous_parameters.clone().unwrap_or_default(),
+        &declared,
+    );
+
+    if issues.is_empty() {
+        return Ok(());
+    }
+
+    Err(format!(
+        "[{}] invalid parameters:\n - {}",
+        s.name,
+        issues.join("\n - ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_echo_errors() {
+        let declared = HashMap::from([(
+            "Password".to_string(),
+            template::TemplateParameter {
+                param_type: "String".to_string(),
+                allowed_pattern: Some("[a-z]+".to_string()),
+                allowed_values: Some(vec!["hunter".to_string()]),
+                no_echo: true,
+                ..Default::default()
+            },
+        )]);
+
+        let values = HashMap::from([("Password".to_string(), Value::from("S3cret!"))]);
+        let issues = validate(&values, &[], &declared);
+        assert_eq!(issues.len(), 2);
+        for issue in issues.iter() {
+            assert!(!issue.contains("S3cret!"), "value shown in: {}", issue);
+            assert!(issue.contains("[****]"), "value not masked in: {}", issue);
+        }
+
+        let values = HashMap::from([("Password".to_string(), Value::from(vec!["S3cret!"]))]);
+        let err = validate(&values, &[], &declared).join("\n");
+        assert!(!err.contains("S3cret!"), "value shown in: {}", err);
+    }
+}
@@ -0,0 +1,616 @@
+use aws_sdk_cloudformation::primitives::DateTime;
+use aws_sdk_cloudformation::types::{ResourceStatus, StackEvent};
+use aws_sdk_cloudformation::Client;
+use colored::Colorize;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::collections::HashSet;
+use std::future::Future;
+use std::io::{self, Write};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::output::{self, human};
+
+// the lines of every stack share one MultiProgress, so stacks watched one
+// after the other or at the same time each keep a line of their own
+static MULTI: OnceLock<MultiProgress> = OnceLock::new();
+
+fn multi() -> &'static MultiProgress {
+    MULTI.get_or_init(|| MultiProgress::with_draw_target(ProgressDrawTarget::stderr()))
+}
+
+// suspend hides the stack lines while f prints or prompts
+pub fn suspend<F: FnOnce() -> R, R>(f: F) -> R {
+    multi().suspend(f)
+}
+
+// Logs writes log lines above the stack lines instead of through them
+pub struct Logs;
+
+impl Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        suspend(|| io::stderr().write_all(buf))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+// StackLine is the line of a stack in the progress display, with the
+// status of the stack, how long the operation has been running and the
+// resources it is done with. The resources of the operation are listed
+// in a table under the line
+pub struct StackLine {
+    stack_name: String,
+    line: ProgressBar,
+    table: Option<ProgressBar>,
+    started: Instant,
+}
+
+impl StackLine {
+    pub fn new(stack_name: &str) -> Self {
+        let line = multi().add(ProgressBar::new_spinner());
+        let style = ProgressStyle::with_template("{spinner:.green} {prefix} {msg} {elapsed:.dim}")
+            .unwrap()
+            .tick_strings(&[
+                "[ ●    ]",
+                "[  ●   ]",
+                "[   ●  ]",
+                "[    ● ]",
+                "[     ●]",
+                "[    ● ]",
+                "[   ●  ]",
+                "[  ●   ]",
+                "[ ●    ]",
+                "[●     ]",
+                "",
+            ]);
+        line.set_style(style);
+        line.set_prefix(format!("[{}]", stack_name.cyan()));
+        line.enable_steady_tick(Duration::from_millis(100));
+
+        StackLine {
+            stack_name: stack_name.to_string(),
+            line,
+            table: None,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn update(&self, status: &str, done: usize, total: usize) {
+        self.line
+            .set_message(format!("{} {}", status, resources(done, total)));
+    }
+
+    // set_rows replaces the rows of the table under the line
+    pub fn set_rows(&mut self, rows: &[String]) {
+        if rows.is_empty() {
+            return;
+        }
+
+        let table = self.table.get_or_insert_with(|| {
+            let table = multi().insert_after(&self.line, ProgressBar::new_spinner());
+            table.set_style(ProgressStyle::with_template("{msg}").unwrap());
+            table
+        });
+        table.set_message(rows.join("\n"));
+    }
+
+    // finish collapses the line into a summary of the operation. When the
+    // operation failed, the table is narrowed down to the given failures,
+    // otherwise it is cleared
+    pub fn finish(
+        self,
+        status: &str,
+        done: usize,
+        total: usize,
+        failed: bool,
+        failures: &[String],
+    ) {
+        let summary = format!(
+            "{} {} in {}, {}",
+            if failed { "✗".red() } else { "✓".green() },
+            status,
+            output::format_duration(self.started.elapsed().as_millis() as u64),
+            resources(done, total)
+        );
+
+        self.line
+            .set_style(ProgressStyle::with_template("{prefix} {msg}").unwrap());
+        self.line.finish_with_message(summary.clone());
+
+        if let Some(table) = &self.table {
+            match failed && !failures.is_empty() {
+                true => table.finish_with_message(failures.join("\n")),
+                false => table.finish_and_clear(),
+            }
+        }
+
+        // without a terminal the lines are never drawn, the summary is
+        // printed once the operation is over instead
+        if multi().is_hidden() {
+            human!("[{}] {}", self.stack_name.cyan(), summary);
+            if failed && !failures.is_empty() {
+                human!("{}", failures.join("\n"));
+            }
+        }
+    }
+}
+
+fn resources(done: usize, total: usize) -> String {
+    format!("{}/{} resources", done, total)
+        .truecolor(96, 96, 96)
+        .to_string()
+}
+
+// Resource is a resource touched by an operation
+pub struct Resource {
+    pub logical_id: String,
+    pub resource_type: String,
+    // statuses the resource went through, oldest first
+    pub statuses: Vec<ResourceStatus>,
+    // reason of the last failure of the resource
+    pub reason: Option<String>,
+    started: Option<DateTime>,
+    updated: Option<DateTime>,
+}
+
+impl Resource {
+    pub fn is_done(&self) -> bool {
+        self.statuses
+            .last()
+            .is_some_and(|s| !s.as_str().ends_with("_IN_PROGRESS"))
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.statuses
+            .iter()
+            .any(|s| s.as_str().ends_with("_FAILED"))
+    }
+
+    // duration returns how long the resource took, or has been
+    // taking so far, in milliseconds
+    pub fn duration(&self) -> u64 {
+        let end = match self.is_done() {
+            true => self.updated,
+            false => Some(DateTime::from(SystemTime::now())),
+        };
+        elapsed(self.started.as_ref(), end.as_ref())
+    }
+}
+
+// Operation collects the events of the stack operation being watched.
+// Operations kloi requests carry a client request token, and every event
+// of the operation carries it too. Operations started by someone else are
+// followed from the latest event starting an operation of their kind.
+// Events of earlier operations on the stack are left out either way
+pub struct Operation {
+    stack_name: String,
+    // status of the stack event starting the operation, for eg: UPDATE_IN_PROGRESS
+    start_status: String,
+    // client request tokens of the requests behind the operation
+    tokens: Vec<String>,
+    started: bool,
+    seen: HashSet<String>,
+    // events of the operation, oldest first
+    pub events: Vec<StackEvent>,
+}
+
+impl Operation {
+    pub fn new(stack_name: &str, start_status: &str, token: Option<&str>) -> Self {
+        Operation {
+            stack_name: stack_name.to_string(),
+            start_status: start_status.to_string(),
+            tokens: token.map(|t| vec![t.to_string()]).unwrap_or_default(),
+            started: false,
+            seen: HashSet::new(),
+            events: Vec::new(),
+        }
+    }
+
+    // accept follows the events of a request taking over the
+    // operation too, for eg: cancelling an update
+    pub fn accept(&mut self, token: &str) {
+        self.tokens.push(token.to_string());
+    }
+
+    // poll reads the events emitted since the last poll, oldest first
+    pub async fn poll(&mut self, client: &Client) -> Result<Vec<StackEvent>, String> {
+        let stack_name = self.stack_name.clone();
+        self.poll_pages(|next_token| {
+            let stack_name = stack_name.clone();
+            async move {
+                match client
+                    .describe_stack_events()
+                    .stack_name(&stack_name)
+                    .set_next_token(next_token)
+                    .send()
+                    .await
+                {
+                    Ok(r) => Ok((
+                        r.stack_events().to_vec(),
+                        r.next_token().map(|t| t.to_string()),
+                    )),
+                    Err(e) => {
+                        let err = e.into_service_error();
+                        // deleted stacks have no events left to read
+                        if err
+                            .meta()
+                            .message()
+                            .unwrap_or_default()
+                            .contains("does not exist")
+                        {
+                            return Ok((Vec::new(), None));
+                        }
+                        Err(format!(
+                            "[{}] error describing stack events: {}",
+                            stack_name, err
+                        ))
+                    }
+                }
+            }
+        })
+        .await
+    }
+
+    // poll_pages reads pages of events, newest first, until it reaches
+    // events that are already known or don't belong to the operation.
+    // fetch returns a page and the token of the next page
+    pub async fn poll_pages<F, Fut>(&mut self, mut fetch: F) -> Result<Vec<StackEvent>, String>
+    where
+        F: FnMut(Option<String>) -> Fut,
+        Fut: Future<Output = Result<(Vec<StackEvent>, Option<String>), String>>,
+    {
+        let mut fresh = Vec::new();
+        let mut next_token = None;
+        let exhausted = loop {
+            let (page, next) = fetch(next_token).await?;
+            if self.scan(&page, &mut fresh) {
+                break false;
+            }
+            match next {
+                Some(t) => next_token = Some(t),
+                None => break true,
+            }
+        };
+
+        Ok(self.record(fresh, exhausted))
+    }
+
+    // scan takes the events of a page that are new to the operation, and
+    // returns true once the older events can't belong to it
+    fn scan(&self, page: &[StackEvent], fresh: &mut Vec<StackEvent>) -> bool {
+        for e in page {
+            if self.seen.contains(e.event_id().unwrap_or_default()) {
+                return true;
+            }
+
+            if !self.tokens.is_empty() {
+                // operations on a stack don't overlap, so the events of
+                // the operation are the newest ones, up to the first
+                // event of another request
+                let ours = e
+                    .client_request_token()
+                    .is_some_and(|t| self.tokens.iter().any(|o| o == t));
+                if !ours {
+                    return true;
+                }
+                fresh.push(e.clone());
+                continue;
+            }
+
+            fresh.push(e.clone());
+            if self.is_start(e) {
+                return true;
+            }
+        }
+        false
+    }
+
+    // record keeps the new events of the operation, oldest first
+    fn record(&mut self, mut fresh: Vec<StackEvent>, exhausted: bool) -> Vec<StackEvent> {
+        if !self.started && self.tokens.is_empty() {
+            match fresh.iter().position(|e| self.is_start(e)) {
+                Some(i) => fresh.truncate(i + 1),
+                // the whole history was read without finding the start
+                // of the operation, only the events after now are kept
+                None if exhausted => {
+                    for e in fresh.iter() {
+                        self.seen
+                            .insert(e.event_id().unwrap_or_default().to_string());
+                    }
+                    self.started = true;
+                    return Vec::new();
+                }
+                None => return Vec::new(),
+            }
+        }
+        if !fresh.is_empty() {
+            self.started = true;
+        }
+
+        fresh.reverse();
+        for e in fresh.iter() {
+            self.seen
+                .insert(e.event_id().unwrap_or_default().to_string());
+        }
+        self.events.extend(fresh.iter().cloned());
+        fresh
+    }
+
+    // resources returns the resources touched by the operation,
+    // in the order the operation started working on them
+    pub fn resources(&self) -> Vec<Resource> {
+        let mut resources: Vec<Resource> = Vec::new();
+        for e in self.events.iter().filter(|e| !self.is_stack(e)) {
+            let logical_id = e.logical_resource_id().unwrap_or_default();
+            let i = match resources.iter().position(|r| r.logical_id == logical_id) {
+                Some(i) => i,
+                None => {
+                    resources.push(Resource {
+                        logical_id: logical_id.to_string(),
+                        resource_type: e.resource_type().unwrap_or_default().to_string(),
+                        statuses: Vec::new(),
+                        reason: None,
+                        started: e.timestamp().cloned(),
+                        updated: None,
+                    });
+                    resources.len() - 1
+                }
+            };
+
+            let resource = &mut resources[i];
+            if let Some(status) = e.resource_status() {
+                if status.as_str().ends_with("_FAILED") {
+                    resource.reason = e.resource_status_reason().map(|r| r.to_string());
+                }
+                resource.statuses.push(status.clone());
+            }
+            resource.updated = e.timestamp().cloned();
+        }
+        resources
+    }
+
+    // progress returns how many of the resources touched by the operation
+    // are done, and how many resources it touched
+    pub fn progress(&self) -> (usize, usize) {
+        let resources = self.resources();
+        let done = resources.iter().filter(|r| r.is_done()).count();
+        (done, resources.len())
+    }
+
+    // took returns how long the resource of an event took, in milliseconds,
+    // if the event ends the work on the resource
+    pub fn took(&self, e: &StackEvent) -> Option<u64> {
+        if self.is_stack(e)
+            || e.resource_status()
+                .is_none_or(|s| s.as_str().ends_with("_IN_PROGRESS"))
+        {
+            return None;
+        }
+
+        let first = self
+            .events
+            .iter()
+            .find(|o| o.logical_resource_id() == e.logical_resource_id())?;
+        Some(elapsed(first.timestamp(), e.timestamp()))
+    }
+
+    pub fn is_stack(&self, e: &StackEvent) -> bool {
+        e.logical_resource_id() == Some(self.stack_name.as_str())
+            && e.resource_type() == Some("AWS::CloudFormation::Stack")
+    }
+
+    fn is_start(&self, e: &StackEvent) -> bool {
+        self.is_stack(e) && e.resource_status().map(|s| s.as_str()) == Some(&self.start_status)
+    }
+}
+
+// elapsed returns the milliseconds between two event timestamps
+fn elapsed(start: Option<&DateTime>, end: Option<&DateTime>) -> u64 {
+    match (start, end) {
+        (Some(start), Some(end)) => {
+            let millis = |d: &DateTime| d.secs() * 1000 + d.subsec_nanos() as i64 / 1_000_000;
+            (millis(end) - millis(start)).max(0) as u64
+        }
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::{ready, Ready};
+
+    type Page = Result<(Vec<StackEvent>, Option<String>), String>;
+
+    fn event(
+        id: &str,
+        logical_id: &str,
+        status: ResourceStatus,
+        token: Option<&str>,
+    ) -> StackEvent {
+        let resource_type = match logical_id {
+            "app" => "AWS::CloudFormation::Stack",
+            _ => "AWS::S3::Bucket",
+        };
+        StackEvent::builder()
+            .event_id(id)
+            .logical_resource_id(logical_id)
+            .resource_type(resource_type)
+            .resource_status(status)
+            .set_client_request_token(token.map(|t| t.to_string()))
+            .build()
+    }
+
+    // pages serves the events of a stack, newest first, two per page
+    fn pages(events: Vec<StackEvent>) -> impl FnMut(Option<String>) -> Ready<Page> {
+        move |next_token| {
+            let start: usize = next_token.map(|t| t.parse().unwrap()).unwrap_or(0);
+            let end = (start + 2).min(events.len());
+            let next = (end < events.len()).then(|| end.to_string());
+            ready(Ok((events[start..end].to_vec(), next)))
+        }
+    }
+
+    fn ids(events: &[StackEvent]) -> Vec<&str> {
+        events.iter().filter_map(|e| e.event_id()).collect()
+    }
+
+    #[test]
+    fn test_poll_token() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let previous = vec![
+            event("old-3", "app", ResourceStatus::UpdateComplete, Some("old")),
+            event("old-2", "Bucket", ResourceStatus::UpdateFailed, Some("old")),
+            event(
+                "old-1",
+                "app",
+                ResourceStatus::UpdateInProgress,
+                Some("old"),
+            ),
+        ];
+
+        // the request was sent, but its operation has no events yet.
+        // The events of the previous operation are not picked up
+        let mut operation = Operation::new("app", "UPDATE_IN_PROGRESS", Some("new"));
+        let fresh = rt.block_on(operation.poll_pages(pages(previous.clone())));
+        assert_eq!(ids(&fresh.unwrap()), Vec::<&str>::new());
+
+        let mut events = vec![
+            event(
+                "new-2",
+                "Bucket",
+                ResourceStatus::UpdateInProgress,
+                Some("new"),
+            ),
+            event(
+                "new-1",
+                "app",
+                ResourceStatus::UpdateInProgress,
+                Some("new"),
+            ),
+        ];
+        events.extend(previous.clone());
+        let fresh = rt.block_on(operation.poll_pages(pages(events.clone())));
+        assert_eq!(ids(&fresh.unwrap()), vec!["new-1", "new-2"]);
+
+        // events of a cancel request are followed once accepted
+        operation.accept("cancel");
+        events.insert(
+            0,
+            event(
+                "new-3",
+                "Bucket",
+                ResourceStatus::UpdateComplete,
+                Some("new"),
+            ),
+        );
+        events.insert(
+            0,
+            event(
+                "new-4",
+                "app",
+                ResourceStatus::UpdateRollbackInProgress,
+                Some("cancel"),
+            ),
+        );
+        let fresh = rt.block_on(operation.poll_pages(pages(events)));
+        assert_eq!(ids(&fresh.unwrap()), vec!["new-3", "new-4"]);
+        assert_eq!(
+            ids(&operation.events),
+            vec!["new-1", "new-2", "new-3", "new-4"]
+        );
+    }
+
+    #[test]
+    fn test_poll_start_event() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+
+        // an operation started by someone else is followed from its start
+        // event, even when it is several pages back
+        let mut events: Vec<StackEvent> = (0..9)
+            .rev()
+            .map(|i| {
+                event(
+                    &format!("update-{}", i),
+                    "Bucket",
+                    ResourceStatus::UpdateComplete,
+                    None,
+                )
+            })
+            .collect();
+        events.push(event(
+            "update-start",
+            "app",
+            ResourceStatus::UpdateInProgress,
+            None,
+        ));
+        events.push(event("create", "app", ResourceStatus::CreateComplete, None));
+
+        let mut operation = Operation::new("app", "UPDATE_IN_PROGRESS", None);
+        let fresh = rt
+            .block_on(operation.poll_pages(pages(events.clone())))
+            .unwrap();
+        assert_eq!(fresh.len(), 10);
+        assert_eq!(fresh[0].event_id(), Some("update-start"));
+
+        // later polls only return new events
+        events.insert(
+            0,
+            event("update-9", "Bucket", ResourceStatus::UpdateComplete, None),
+        );
+        let fresh = rt.block_on(operation.poll_pages(pages(events))).unwrap();
+        assert_eq!(ids(&fresh), vec!["update-9"]);
+
+        // without a start event, only the events after the first poll are kept
+        let mut events = vec![event("create", "app", ResourceStatus::CreateComplete, None)];
+        let mut operation = Operation::new("app", "UPDATE_IN_PROGRESS", None);
+        let fresh = rt
+            .block_on(operation.poll_pages(pages(events.clone())))
+            .unwrap();
+        assert!(fresh.is_empty());
+
+        events.insert(
+            0,
+            event("update", "Bucket", ResourceStatus::UpdateInProgress, None),
+        );
+        let fresh = rt.block_on(operation.poll_pages(pages(events))).unwrap();
+        assert_eq!(ids(&fresh), vec!["update"]);
+    }
+
+    #[test]
+    fn test_operation_progress() {
+        let mut operation = Operation::new("app", "UPDATE_IN_PROGRESS", None);
+        operation.events = vec![
+            event("app-1", "app", ResourceStatus::UpdateInProgress, None),
+            event("Bucket-1", "Bucket", ResourceStatus::UpdateInProgress, None),
+            event("Queue-1", "Queue", ResourceStatus::CreateInProgress, None),
+            event("Bucket-2", "Bucket", ResourceStatus::UpdateComplete, None),
+            event("Topic-1", "Topic", ResourceStatus::CreateFailed, None),
+        ];
+
+        // the stack itself is not counted as a resource
+        assert_eq!(operation.progress(), (2, 3));
+
+        let resources = operation.resources();
+        let ids: Vec<&str> = resources.iter().map(|r| r.logical_id.as_str()).collect();
+        assert_eq!(ids, vec!["Bucket", "Queue", "Topic"]);
+        assert_eq!(
+            resources[0].statuses,
+            vec![
+                ResourceStatus::UpdateInProgress,
+                ResourceStatus::UpdateComplete
+            ]
+        );
+        assert!(resources[0].is_done() && !resources[0].is_failed());
+        assert!(!resources[1].is_done());
+        assert!(resources[2].is_failed());
+
+        // only events ending the work on a resource have a duration
+        assert_eq!(operation.took(&operation.events[1]), None);
+        assert_eq!(operation.took(&operation.events[3]), Some(0));
+    }
+}
@@ -8,6 +8,26 @@ use std::collections::HashMap;
 // use std::process::Command;
 use log;
 
+// tag used to mark stacks deployed by kloi
+pub const MANAGED_TAG: &str = "kloi:managed";
+
+// tag used to record the project of the config that deployed the stack
+pub const PROJECT_TAG: &str = "kloi:project";
+
+// tag used to record the time-to-live of ephemeral stacks
+pub const TTL_TAG: &str = "kloi:ttl";
+
+// stateful resource types that apply refuses to replace, unless
+// overridden by stacks.lifecycle(protect_replacement=[...])
+pub const PROTECTED_TYPES: &[&str] = &[
+    "AWS::RDS::DBInstance",
+    "AWS::RDS::DBCluster",
+    "AWS::DynamoDB::Table",
+    "AWS::DynamoDB::GlobalTable",
+    "AWS::S3::Bucket",
+    "AWS::EFS::FileSystem",
+];
+
 #[derive(Debug, Clone, derive_more::Display, Allocative, NoSerialize, ProvidesStaticType)]
 #[allocative(skip)]
 pub struct JSONValues(serde_json::Value);
@@ -25,12 +45,27 @@ pub struct Stack {
     #[allocative(skip)]
     // pub values: Option<HashMap<String, serde_json::Value>>,
     pub values: Option<serde_json::Value>,
+    // the values layer (file or inline) each values key came from
+    pub value_sources: Option<HashMap<String, String>>,
     pub depends_on: Option<Vec<String>>,
-    pub parameters: Option<HashMap<String, String>>,
+    #[allocative(skip)]
+    pub parameters: Option<HashMap<String, serde_json::Value>>,
+    // parameters that keep their currently deployed value
+    pub previous_parameters: Option<Vec<String>>,
     pub region: Option<String>,
     pub capabilities: Option<Vec<String>>,
     pub exec: Option<Hooks>,
     pub custom_resources: Option<Vec<String>>,
+    pub tags: Option<HashMap<String, String>>,
+    pub ttl: Option<String>,
+    pub protect_replacement: Option<Vec<String>>,
+    // service role cloudformation assumes to deploy the stack
+    pub role_arn: Option<String>,
+    // sns topics notified of stack events
+    pub notification_arns: Option<Vec<String>>,
+    pub termination_protection: Option<bool>,
+    // project of the config the stack was added to, set by stacks.project
+    pub project: Option<String>,
     // pub macros: Option<HashMap<String, String>>,
 }
 
@@ -39,6 +74,54 @@ impl<'v> StarlarkValue<'v> for Stack {}
 
 starlark_simple_value!(Stack);
 
+// Inputs are the values and parameters of a stack, see stacks.inputs
+#[derive(Debug, Display, ProvidesStaticType, NoSerialize, Allocative, Clone, Default)]
+#[display("Inputs")]
+#[allocative(skip)]
+pub struct Inputs {
+    pub values: Option<serde_json::Value>,
+    pub value_sources: Option<HashMap<String, String>>,
+    pub parameters: Option<HashMap<String, serde_json::Value>>,
+    pub previous_parameters: Option<Vec<String>>,
+}
+
+#[starlark_value(type = "inputs")]
+impl<'v> StarlarkValue<'v> for Inputs {}
+
+starlark_simple_value!(Inputs);
+
+// Options are the settings a stack is deployed with, see stacks.options
+#[derive(Debug, Display, ProvidesStaticType, NoSerialize, Allocative, Clone, Default)]
+#[display("Options")]
+pub struct Options {
+    pub bucket: Option<String>,
+    pub capabilities: Option<Vec<String>>,
+    pub custom_resources: Option<Vec<String>>,
+    pub tags: Option<HashMap<String, String>>,
+    pub role_arn: Option<String>,
+    pub notification_arns: Option<Vec<String>>,
+}
+
+#[starlark_value(type = "options")]
+impl<'v> StarlarkValue<'v> for Options {}
+
+starlark_simple_value!(Options);
+
+// Lifecycle controls how long a stack lives and how it is
+// protected, see stacks.lifecycle
+#[derive(Debug, Display, ProvidesStaticType, NoSerialize, Allocative, Clone, Default)]
+#[display("Lifecycle")]
+pub struct Lifecycle {
+    pub ttl: Option<String>,
+    pub termination_protection: Option<bool>,
+    pub protect_replacement: Option<Vec<String>>,
+}
+
+#[starlark_value(type = "lifecycle")]
+impl<'v> StarlarkValue<'v> for Lifecycle {}
+
+starlark_simple_value!(Lifecycle);
+
 impl Stack {
     // self.is_dependency_of(s); -> bool
     pub fn is_dependency_of(&self, s: &Stack) -> bool {
@@ -76,6 +159,64 @@ impl Stack {
 
         Ok(self.template.to_string())
     }
+
+    // checks if replacing a resource of the given type needs to be
+    // explicitly allowed. Protected types may end with a * wildcard
+    pub fn is_protected(&self, resource_type: &str) -> bool {
+        let defaults = PROTECTED_TYPES.iter().map(|t| t.to_string()).collect();
+        self.protect_replacement
+            .clone()
+            .unwrap_or(defaults)
+            .iter()
+            .any(|t| match t.strip_suffix('*') {
+                Some(prefix) => resource_type.starts_with(prefix),
+                None => resource_type == t,
+            })
+    }
+
+    // returns the stack tags, including the tags kloi uses
+    // to keep track of the stack (e.g. ttl)
+    pub fn tags(&self) -> HashMap<String, String> {
+        let mut tags = self.tags.clone().unwrap_or_default();
+        tags.insert(MANAGED_TAG.to_string(), "true".to_string());
+        if let Some(project) = &self.project {
+            tags.insert(PROJECT_TAG.to_string(), project.clone());
+        }
+        if let Some(ttl) = &self.ttl {
+            tags.insert(TTL_TAG.to_string(), ttl.clone());
+        }
+        tags
+    }
+}
+
+// parse_ttl parses a ttl string such as "30m", "72h" or "7d"
+// into a chrono duration
+pub fn parse_ttl(ttl: &str) -> Result<chrono::Duration, String> {
+    let ttl = ttl.trim();
+    let err = || {
+        format!(
+            "invalid ttl [{}], expected a positive number followed by s, m, h, d or w",
+            ttl
+        )
+    };
+
+    let unit = ttl.chars().last().ok_or_else(err)?;
+    let num = ttl[..ttl.len() - unit.len_utf8()]
+        .parse::<i64>()
+        .map_err(|_| err())?;
+    if num <= 0 {
+        return Err(err());
+    }
+
+    match unit {
+        's' => chrono::Duration::try_seconds(num),
+        'm' => chrono::Duration::try_minutes(num),
+        'h' => chrono::Duration::try_hours(num),
+        'd' => chrono::Duration::try_days(num),
+        'w' => chrono::Duration::try_weeks(num),
+        _ => None,
+    }
+    .ok_or_else(err)
 }
 
 #[derive(Debug, Display, ProvidesStaticType, NoSerialize, Allocative, Clone)]
@@ -0,0 +1,360 @@
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+// TemplateParameter is a parameter declared in
+// the Parameters section of a cloudformation template
+#[derive(Debug, Clone, Default)]
+pub struct TemplateParameter {
+    pub param_type: String,
+    pub default: Option<String>,
+    pub allowed_values: Option<Vec<String>>,
+    pub allowed_pattern: Option<String>,
+    pub min_length: Option<u64>,
+    pub max_length: Option<u64>,
+    pub min_value: Option<f64>,
+    pub max_value: Option<f64>,
+    pub description: Option<String>,
+    pub no_echo: bool,
+}
+
+// parse parses a yaml or json cloudformation template into json.
+// yaml short-form intrinsic functions (e.g. !Ref, !Sub) are expanded
+// to their long form so both formats produce the same document
+pub fn parse(template: &str) -> Result<Value, String> {
+    let doc: serde_yaml::Value =
+        serde_yaml::from_str(template).map_err(|e| format!("failed to parse template: {}", e))?;
+    Ok(to_json(doc))
+}
+
+fn to_json(v: serde_yaml::Value) -> Value {
+    match v {
+        serde_yaml::Value::Null => Value::Null,
+        serde_yaml::Value::Bool(b) => Value::Bool(b),
+        serde_yaml::Value::Number(n) => serde_json::to_value(n).unwrap_or(Value::Null),
+        serde_yaml::Value::String(s) => Value::String(s),
+        serde_yaml::Value::Sequence(s) => Value::Array(s.into_iter().map(to_json).collect()),
+        serde_yaml::Value::Mapping(m) => Value::Object(
+            m.into_iter()
+                .map(|(k, v)| (key_string(k), to_json(v)))
+                .collect(),
+        ),
+        serde_yaml::Value::Tagged(t) => {
+            let tag = t.tag.to_string();
+            let name = tag.trim_start_matches('!');
+            let value = match (name, t.value) {
+                // !GetAtt Resource.Attribute -> [Resource, Attribute]
+                ("GetAtt", serde_yaml::Value::String(s)) => match s.split_once('.') {
+                    Some((res, attr)) => Value::Array(vec![res.into(), attr.into()]),
+                    None => Value::String(s),
+                },
+                (_, v) => to_json(v),
+            };
+
+            let key = match name {
+                "Ref" | "Condition" => name.to_string(),
+                _ => format!("Fn::{}", name),
+            };
+
+            let mut obj = Map::new();
+            obj.insert(key, value);
+            Value::Object(obj)
+        }
+    }
+}
+
+fn key_string(k: serde_yaml::Value) -> String {
+    match k {
+        serde_yaml::Value::String(s) => s,
+        serde_yaml::Value::Bool(b) => b.to_string(),
+        serde_yaml::Value::Number(n) => n.to_string(),
+        other => serde_yaml::to_string(&other)
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+    }
+}
+
+// normalize parses a template and renders it as yaml with sorted keys,
+// so that templates differing only in format or key order render the same
+pub fn normalize(template: &str) -> Result<String, String> {
+    serde_yaml::to_string(&sorted(parse(template)?))
+        .map_err(|e| format!("failed to render template: {}", e))
+}
+
+fn sorted(v: Value) -> Value {
+    match v {
+        Value::Object(m) => {
+            let mut entries: Vec<(String, Value)> = m.into_iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            Value::Object(entries.into_iter().map(|(k, v)| (k, sorted(v))).collect())
+        }
+        Value::Array(a) => Value::Array(a.into_iter().map(sorted).collect()),
+        other => other,
+    }
+}
+
+// parameters returns the parameters declared by a parsed template
+pub fn parameters(template: &Value) -> HashMap<String, TemplateParameter> {
+    let mut params = HashMap::new();
+    let Some(section) = template.get("Parameters").and_then(|p| p.as_object()) else {
+        return params;
+    };
+
+    for (name, p) in section {
+        params.insert(
+            name.clone(),
+            TemplateParameter {
+                param_type: p
+                    .get("Type")
+                    .and_then(|t| t.as_str())
+                    .unwrap_or("String")
+                    .to_string(),
+                default: p.get("Default").map(scalar_string),
+                allowed_values: p
+                    .get("AllowedValues")
+                    .and_then(|v| v.as_array())
+                    .map(|v| v.iter().map(scalar_string).collect()),
+                allowed_pattern: p
+                    .get("AllowedPattern")
+                    .and_then(|v| v.as_str())
+                    .map(|v| v.to_string()),
+                min_length: p.get("MinLength").and_then(number).map(|n| n as u64),
+                max_length: p.get("MaxLength").and_then(number).map(|n| n as u64),
+                min_value: p.get("MinValue").and_then(number),
+                max_value: p.get("MaxValue").and_then(number),
+                description: p
+                    .get("Description")
+                    .and_then(|v| v.as_str())
+                    .map(|v| v.to_string()),
+                no_echo: p
+                    .get("NoEcho")
+                    .map(|v| scalar_string(v).eq_ignore_ascii_case("true"))
+                    .unwrap_or(false),
+            },
+        );
+    }
+
+    params
+}
+
+// scalar_string renders a template scalar the way cloudformation reads it
+fn scalar_string(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        Value::Array(a) => a.iter().map(scalar_string).collect::<Vec<_>>().join(","),
+        other => other.to_string(),
+    }
+}
+
+// number reads a numeric template attribute, which may be quoted
+fn number(v: &Value) -> Option<f64> {
+    match v {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+// DiffAction is how a resource differs between two templates
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffAction {
+    Added,
+    Removed,
+    Changed,
+}
+
+// PropertyChange is a changed attribute of a resource, identified
+// by its dotted path, for eg: Properties.Tags[0].Value
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyChange {
+    pub path: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+// ResourceDiff is the difference of a resource between two templates
+#[derive(Debug, Clone)]
+pub struct ResourceDiff {
+    pub logical_id: String,
+    pub resource_type: String,
+    pub action: DiffAction,
+    pub changes: Vec<PropertyChange>,
+}
+
+// diff_resources compares the Resources sections of two parsed
+// templates by logical id, sorted by logical id
+pub fn diff_resources(old: &Value, new: &Value) -> Vec<ResourceDiff> {
+    let empty = Map::new();
+    let resources = |t: &Value| -> Map<String, Value> {
+        t.get("Resources")
+            .and_then(|r| r.as_object())
+            .unwrap_or(&empty)
+            .clone()
+    };
+    let (old, new) = (resources(old), resources(new));
+
+    let mut ids: Vec<&String> = old.keys().chain(new.keys()).collect();
+    ids.sort();
+    ids.dedup();
+
+    let mut diffs = Vec::new();
+    for id in ids {
+        let (action, resource) = match (old.get(id), new.get(id)) {
+            (None, Some(r)) => (DiffAction::Added, r),
+            (Some(r), None) => (DiffAction::Removed, r),
+            (Some(o), Some(n)) if o != n => (DiffAction::Changed, n),
+            _ => continue,
+        };
+
+        let mut changes = Vec::new();
+        if action == DiffAction::Changed {
+            diff_values("", old.get(id), new.get(id), &mut changes);
+        }
+
+        diffs.push(ResourceDiff {
+            logical_id: id.clone(),
+            resource_type: resource
+                .get("Type")
+                .and_then(|t| t.as_str())
+                .unwrap_or("-")
+                .to_string(),
+            action,
+            changes,
+        });
+    }
+
+    diffs
+}
+
+// diff_values records the leaf values that differ between two values,
+// lists of different lengths are recorded as a whole
+fn diff_values(
+    path: &str,
+    old: Option<&Value>,
+    new: Option<&Value>,
+    out: &mut Vec<PropertyChange>,
+) {
+    match (old, new) {
+        (Some(Value::Object(o)), Some(Value::Object(n))) => {
+            let mut keys: Vec<&String> = o.keys().chain(n.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for k in keys {
+                let path = if path.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{}.{}", path, k)
+                };
+                diff_values(&path, o.get(k), n.get(k), out);
+            }
+        }
+        (Some(Value::Array(o)), Some(Value::Array(n))) if o.len() == n.len() => {
+            for (i, (o, n)) in o.iter().zip(n).enumerate() {
+                diff_values(&format!("{}[{}]", path, i), Some(o), Some(n), out);
+            }
+        }
+        (o, n) if o != n => out.push(PropertyChange {
+            path: path.to_string(),
+            old: o.cloned(),
+            new: n.cloned(),
+        }),
+        _ => {}
+    }
+}
+
+// check_import checks that a resource can be imported into a stack with
+// the given template. Cloudformation requires imported resources to be
+// declared with their type and a DeletionPolicy
+pub fn check_import(template: &Value, logical_id: &str, resource_type: &str) -> Result<(), String> {
+    let resource = template
+        .get("Resources")
+        .and_then(|r| r.get(logical_id))
+        .ok_or_else(|| format!("resource [{}] is not declared in the template", logical_id))?;
+
+    let declared = resource.get("Type").and_then(|t| t.as_str()).unwrap_or("-");
+    if declared != resource_type {
+        return Err(format!(
+            "resource [{}] is declared as {}, not {}",
+            logical_id, declared, resource_type
+        ));
+    }
+
+    if resource.get("DeletionPolicy").is_none() {
+        return Err(format!(
+            "resource [{}] has no DeletionPolicy, which is required to import it",
+            logical_id
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn test_normalize_template() {
+        let yaml = indoc! {r#"
+            Resources:
+              Queue:
+                Type: AWS::SQS::Queue
+                Properties:
+                  QueueName: !Sub "${AWS::StackName}-queue"
+                  DelaySeconds: 5
+        "#};
+        let json = r#"{"Resources": {"Queue": {"Properties": {"DelaySeconds": 5,
+            "QueueName": {"Fn::Sub": "${AWS::StackName}-queue"}}, "Type": "AWS::SQS::Queue"}}}"#;
+
+        assert_eq!(normalize(yaml).unwrap(), normalize(json).unwrap());
+    }
+
+    #[test]
+    fn test_diff_resources() {
+        let old = parse(indoc! {r#"
+            Resources:
+              Queue:
+                Type: AWS::SQS::Queue
+                Properties:
+                  DelaySeconds: 5
+                  Tags: [{Key: team, Value: platform}]
+              Topic:
+                Type: AWS::SNS::Topic
+        "#})
+        .unwrap();
+        let new = parse(indoc! {r#"
+            Resources:
+              Bucket:
+                Type: AWS::S3::Bucket
+              Queue:
+                Type: AWS::SQS::Queue
+                Properties:
+                  DelaySeconds: 5
+                  Tags: [{Key: team, Value: data}]
+        "#})
+        .unwrap();
+
+        let diffs = diff_resources(&old, &new);
+        let actions: Vec<(&str, &DiffAction)> = diffs
+            .iter()
+            .map(|d| (d.logical_id.as_str(), &d.action))
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                ("Bucket", &DiffAction::Added),
+                ("Queue", &DiffAction::Changed),
+                ("Topic", &DiffAction::Removed),
+            ]
+        );
+        assert_eq!(
+            diffs[1].changes,
+            vec![PropertyChange {
+                path: "Properties.Tags[0].Value".to_string(),
+                old: Some("platform".into()),
+                new: Some("data".into()),
+            }]
+        );
+    }
+}
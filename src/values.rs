@@ -0,0 +1,151 @@
+use crate::config::Source;
+use serde_json::Value;
+use std::collections::HashMap;
+
+// source name used for the inline stacks.inputs values
+pub const INLINE_SOURCE: &str = "values";
+
+// ListMerge defines how lists are combined when
+// the same key is defined in multiple values layers
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListMerge {
+    // the later list replaces the earlier list
+    Replace,
+    // the later list is appended to the earlier list
+    Append,
+    // like append, but items already present are skipped
+    Unique,
+}
+
+impl TryFrom<&str> for ListMerge {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "replace" => Ok(ListMerge::Replace),
+            "append" => Ok(ListMerge::Append),
+            "unique" => Ok(ListMerge::Unique),
+            _ => Err(format!(
+                "invalid list merge strategy [{}], expected one of: replace, append, unique",
+                s
+            )),
+        }
+    }
+}
+
+// Layers merges values files and inline values in order
+// while recording which layer each final key came from
+pub struct Layers {
+    pub values: Value,
+    pub sources: HashMap<String, String>,
+    strategy: ListMerge,
+}
+
+impl Layers {
+    pub fn new(strategy: ListMerge) -> Self {
+        Layers {
+            values: Value::Object(Default::default()),
+            sources: HashMap::new(),
+            strategy,
+        }
+    }
+
+    // add_file reads a yaml or json values file and merges it
+    pub fn add_file(&mut self, path: &str) -> Result<(), String> {
+        let content = path.to_string().read()?;
+        let values: Value = serde_yaml::from_str(&content)
+            .map_err(|e| format!("failed to parse values file [{}]: {}", path, e))?;
+
+        // an empty file parses as null
+        if values.is_null() {
+            return Ok(());
+        }
+
+        if !values.is_object() {
+            return Err(format!(
+                "values file [{}] must contain a mapping at the top level",
+                path
+            ));
+        }
+
+        self.add(values, path);
+        Ok(())
+    }
+
+    // add merges a values layer on top of the current values
+    pub fn add(&mut self, values: Value, source: &str) {
+        let mut base = std::mem::take(&mut self.values);
+        self.merge(&mut base, values, source, "");
+        self.values = base;
+    }
+
+    fn merge(&mut self, base: &mut Value, overlay: Value, source: &str, path: &str) {
+        match (base, overlay) {
+            (Value::Object(base), Value::Object(overlay)) => {
+                for (k, v) in overlay {
+                    let key_path = join_path(path, &k);
+                    match base.get_mut(&k) {
+                        Some(existing) => self.merge(existing, v, source, &key_path),
+                        None => {
+                            self.record(&key_path, &v, source);
+                            base.insert(k, v);
+                        }
+                    }
+                }
+            }
+            (Value::Array(base), Value::Array(overlay)) if self.strategy != ListMerge::Replace => {
+                for v in overlay {
+                    if self.strategy == ListMerge::Unique && base.contains(&v) {
+                        continue;
+                    }
+                    base.push(v);
+                }
+
+                let sources = match self.sources.get(path) {
+                    Some(s) if !s.split(", ").any(|s| s == source) => format!("{}, {}", s, source),
+                    Some(s) => s.clone(),
+                    None => source.to_string(),
+                };
+                self.sources.insert(path.to_string(), sources);
+            }
+            (base, overlay) => {
+                self.forget(path);
+                self.record(path, &overlay, source);
+                *base = overlay;
+            }
+        }
+    }
+
+    // record sets the source of every leaf key in a value
+    fn record(&mut self, path: &str, value: &Value, source: &str) {
+        match value {
+            Value::Object(o) if !o.is_empty() => {
+                for (k, v) in o {
+                    self.record(&join_path(path, k), v, source);
+                }
+            }
+            _ => {
+                self.sources.insert(path.to_string(), source.to_string());
+            }
+        }
+    }
+
+    // forget removes the sources recorded for a key and its children
+    fn forget(&mut self, path: &str) {
+        let prefix = format!("{}.", path);
+        self.sources
+            .retain(|k, _| k != path && !k.starts_with(prefix.as_str()));
+    }
+}
+
+fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        return key.to_string();
+    }
+    format!("{}.{}", path, key)
+}
+
+// lookup returns the value at a dotted key path
+pub fn lookup<'a>(values: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(values, |v, k| v.get(k))
+}